@@ -0,0 +1,239 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use duckdb::types::Value;
+use duckdb::Connection;
+use lazy_static::lazy_static;
+use pgwire::api::Type;
+use pgwire::error::PgWireResult;
+use postgres_types::Kind;
+
+use crate::error::into_pg_error;
+
+// PostgreSQL 中用户自定义对象的 oid 从这里开始, 避免和内置类型冲突
+const FIRST_NORMAL_OBJECT_ID: u32 = 16384;
+
+// 任意会话执行 DDL 后加一. 各会话缓存的预编译语句和结果列在版本变化后作废
+static CATALOG_VERSION: AtomicU64 = AtomicU64::new(0);
+
+pub fn catalog_version() -> u64 {
+    CATALOG_VERSION.load(Ordering::Acquire)
+}
+
+pub fn bump_catalog_version() {
+    CATALOG_VERSION.fetch_add(1, Ordering::AcqRel);
+}
+
+lazy_static! {
+    // 已经分配过的 oid, 以对象的全名为 key. 按顺序分配, 不同对象的 oid 不会冲突
+    static ref OBJECT_OIDS: Mutex<HashMap<Vec<String>, u32>> = Mutex::new(HashMap::new());
+}
+
+// 用 CREATE TYPE 创建的 ENUM. oid 按类型的全名分配, 同一个类型在所有会话中保持不变
+#[derive(Debug, Clone, PartialEq)]
+pub struct PgEnum {
+    pub oid: u32,
+    pub schema_oid: i64,
+    pub schema: String,
+    pub name: String,
+    pub labels: Vec<String>,
+}
+
+impl PgEnum {
+    pub fn pg_type(&self) -> Type {
+        Type::new(self.name.clone(), self.oid, Kind::Enum(self.labels.clone()), self.schema.clone())
+    }
+
+    fn label_oid(&self, label: &str) -> u32 {
+        object_oid(&["label", &self.oid.to_string(), label])
+    }
+}
+
+// 同一个名字总是得到同一个 oid, 新的名字使用下一个未分配的 oid
+fn object_oid(name: &[&str]) -> u32 {
+    let mut oids = OBJECT_OIDS.lock().unwrap();
+    let next_oid = FIRST_NORMAL_OBJECT_ID + oids.len() as u32;
+    *oids.entry(name.iter().map(|part| part.to_string()).collect()).or_insert(next_oid)
+}
+
+// 按 label 查找 ENUM. 多个 ENUM 的 label 完全相同时无法区分, 返回第一个
+pub fn find_enum_by_labels<'a>(enums: &'a [PgEnum], labels: &[String]) -> Option<&'a PgEnum> {
+    enums.iter().find(|pg_enum| pg_enum.labels == labels)
+}
+
+// DESCRIBE 给出的类型名
+pub fn find_enum_by_name<'a>(enums: &'a [PgEnum], type_name: &str) -> Option<&'a PgEnum> {
+    let type_name = type_name.trim_matches('"');
+    enums.iter().find(|pg_enum| pg_enum.name.eq_ignore_ascii_case(type_name))
+}
+
+// 按 DESCRIBE 等处给出的类型名查找 ENUM. CREATE TYPE 创建的 ENUM 是类型名,
+// 没有名字的 ENUM 是 ENUM('a', 'b'), 按 label 查找
+pub fn find_enum_by_type<'a>(enums: &'a [PgEnum], type_name: &str) -> Option<&'a PgEnum> {
+    match parse_enum_labels(type_name) {
+        Some(labels) => find_enum_by_labels(enums, &labels),
+        None => find_enum_by_name(enums, type_name),
+    }
+}
+
+fn parse_enum_labels(type_name: &str) -> Option<Vec<String>> {
+    let mut chars = type_name.strip_prefix("ENUM(")?.strip_suffix(')')?.chars().peekable();
+    let mut labels = vec![];
+    loop {
+        while chars.next_if_eq(&' ').is_some() {}
+        if chars.next()? != '\'' {
+            return None;
+        }
+        let mut label = String::new();
+        loop {
+            match chars.next()? {
+                '\'' if chars.next_if_eq(&'\'').is_some() => label.push('\''),
+                '\'' => break,
+                c => label.push(c),
+            }
+        }
+        labels.push(label);
+        match chars.next() {
+            None => return Some(labels),
+            Some(',') => {}
+            Some(_) => return None,
+        }
+    }
+}
+
+fn load_enums(conn: &Connection) -> PgWireResult<Vec<PgEnum>> {
+    let query = "SELECT database_name, schema_name, schema_oid, type_name, labels FROM duckdb_types() \
+                 WHERE logical_type = 'ENUM' AND NOT internal ORDER BY database_name, schema_name, type_name";
+    let mut stmt = conn.prepare(query).map_err(|e| into_pg_error(e, query))?;
+    let enums = stmt
+        .query_map([], |row| {
+            let database: String = row.get(0)?;
+            let schema: String = row.get(1)?;
+            let name: String = row.get(3)?;
+            let labels = match row.get::<_, Value>(4)? {
+                Value::List(labels) => labels
+                    .into_iter()
+                    .filter_map(|label| match label {
+                        Value::Text(label) => Some(label),
+                        _ => None,
+                    })
+                    .collect(),
+                _ => vec![],
+            };
+            Ok(PgEnum {
+                oid: object_oid(&["type", &database, &schema, &name]),
+                schema_oid: row.get(2)?,
+                schema,
+                name,
+                labels,
+            })
+        })
+        .and_then(|rows| rows.collect::<Result<Vec<PgEnum>, _>>())
+        .map_err(|e| into_pg_error(e, query))?;
+    Ok(enums)
+}
+
+fn quote_literal(text: &str) -> String {
+    format!("'{}'", text.replace('\'', "''"))
+}
+
+// 用临时视图覆盖 pg_catalog 里的 pg_type 和 pg_enum, 让 client 能通过和结果列相同的 oid 查到 ENUM 的定义.
+// 临时视图只对当前连接可见, 在查找顺序上排在 pg_catalog 之前
+fn create_catalog_views(conn: &Connection, enums: &[PgEnum]) -> PgWireResult<()> {
+    let enum_types: Vec<String> = enums
+        .iter()
+        .map(|pg_enum| {
+            format!(
+                "SELECT {} AS oid, {} AS typname, {} AS typnamespace, 0 AS typowner, 4 AS typlen, true AS typbyval, \
+                 'e' AS typtype, 'E' AS typcategory, true AS typisdefined, ',' AS typdelim, 0 AS typrelid, \
+                 0 AS typelem, 0 AS typarray, 0 AS typbasetype, -1 AS typtypmod, 0 AS typndims, false AS typnotnull",
+                pg_enum.oid,
+                quote_literal(&pg_enum.name),
+                pg_enum.schema_oid,
+            )
+        })
+        .collect();
+    let mut pg_type = "CREATE OR REPLACE TEMP VIEW pg_type AS SELECT * FROM pg_catalog.pg_type \
+                       WHERE oid NOT IN (SELECT type_oid FROM duckdb_types() WHERE logical_type = 'ENUM' AND type_oid IS NOT NULL)"
+        .to_owned();
+    for enum_type in enum_types {
+        pg_type.push_str(" UNION ALL BY NAME ");
+        pg_type.push_str(&enum_type);
+    }
+
+    let mut pg_enum = "CREATE OR REPLACE TEMP VIEW pg_enum AS SELECT NULL::BIGINT AS oid, NULL::BIGINT AS enumtypid, \
+                       NULL::FLOAT AS enumsortorder, NULL::VARCHAR AS enumlabel WHERE false"
+        .to_owned();
+    for enum_type in enums {
+        for (idx, label) in enum_type.labels.iter().enumerate() {
+            pg_enum.push_str(&format!(
+                " UNION ALL SELECT {}, {}, {}, {}",
+                enum_type.label_oid(label),
+                enum_type.oid,
+                idx + 1,
+                quote_literal(label),
+            ));
+        }
+    }
+
+    conn.execute_batch(&pg_type).map_err(|e| into_pg_error(e, &pg_type))?;
+    conn.execute_batch(&pg_enum).map_err(|e| into_pg_error(e, &pg_enum))?;
+    Ok(())
+}
+
+// 重新读取 ENUM 类型并更新模拟的 pg_type / pg_enum, 在会话开始和 catalog 版本变化之后调用
+pub fn refresh_pg_catalog(conn: &Connection) -> PgWireResult<Vec<PgEnum>> {
+    let enums = load_enums(conn)?;
+    create_catalog_views(conn, &enums)?;
+    Ok(enums)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mood() -> PgEnum {
+        PgEnum {
+            oid: object_oid(&["type", "memory", "main", "mood"]),
+            schema_oid: 1,
+            schema: "main".to_owned(),
+            name: "mood".to_owned(),
+            labels: vec!["sad".to_owned(), "ok".to_owned(), "happy".to_owned()],
+        }
+    }
+
+    #[test]
+    fn test_parse_enum_labels() {
+        assert_eq!(parse_enum_labels("ENUM('a', 'b''c', 'd,e')").unwrap(), vec!["a", "b'c", "d,e"]);
+        assert_eq!(parse_enum_labels("ENUM('')").unwrap(), vec![""]);
+        assert_eq!(parse_enum_labels("VARCHAR"), None);
+        assert_eq!(parse_enum_labels("ENUM('a'"), None);
+    }
+
+    #[test]
+    fn test_object_oid() {
+        let mood = object_oid(&["type", "memory", "main", "mood"]);
+        assert_eq!(object_oid(&["type", "memory", "main", "mood"]), mood);
+        assert_ne!(object_oid(&["type", "memory", "main", "color"]), mood);
+        // 名字里的点号不会和分隔符混淆
+        assert_ne!(object_oid(&["type", "memory", "main.mood", ""]), mood);
+        assert!(mood >= FIRST_NORMAL_OBJECT_ID);
+    }
+
+    #[test]
+    fn test_find_enum() {
+        let enums = vec![mood()];
+        let labels = vec!["sad".to_owned(), "ok".to_owned(), "happy".to_owned()];
+        assert_eq!(find_enum_by_labels(&enums, &labels), Some(&enums[0]));
+        assert_eq!(find_enum_by_labels(&enums, &labels[..2]), None);
+        assert_eq!(find_enum_by_name(&enums, "MOOD"), Some(&enums[0]));
+        assert_eq!(find_enum_by_type(&enums, "mood"), Some(&enums[0]));
+        assert_eq!(find_enum_by_type(&enums, "ENUM('sad', 'ok', 'happy')"), Some(&enums[0]));
+        assert_eq!(find_enum_by_type(&enums, "ENUM('sad', 'ok')"), None);
+
+        let pg_type = enums[0].pg_type();
+        assert_eq!(pg_type.oid(), enums[0].oid);
+        assert_eq!(pg_type.kind(), &Kind::Enum(labels));
+    }
+}
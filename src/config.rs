@@ -15,6 +15,29 @@ pub struct FatherDuckConfig {
     pub username: String,
     pub password: String,
     pub path: String,
+    #[serde(default)]
+    pub nested_format: NestedFormat,
+    #[serde(default)]
+    pub json_format: JsonFormat,
+}
+
+// STRUCT / MAP / UNION 返回给客户端的格式
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum NestedFormat {
+    #[default]
+    Json,
+    Jsonb,
+    Record,
+}
+
+// JSON 列返回给客户端的类型
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum JsonFormat {
+    #[default]
+    Json,
+    Jsonb,
 }
 pub static MEMORY_PATH: &str = ":memory:";
 
@@ -53,5 +76,7 @@ mod tests {
         assert_eq!(FATHERDUCK_CONFIG.port, 5432);
         assert_eq!(FATHERDUCK_CONFIG.username, "fatherduck");
         assert_eq!(FATHERDUCK_CONFIG.password, "fatherduck");
+        assert_eq!(FATHERDUCK_CONFIG.nested_format, NestedFormat::Json);
+        assert_eq!(FATHERDUCK_CONFIG.json_format, JsonFormat::Json);
     }
 }
@@ -1,21 +1,238 @@
-use duckdb::Connection;
+use std::collections::{HashMap, VecDeque};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{mpsc, Arc};
+use std::thread;
 
+use chrono_tz::Tz;
+use duckdb::{Connection, Statement};
+use pgwire::api::results::FieldInfo;
+use pgwire::error::{PgWireError, PgWireResult};
+use tokio::sync::oneshot;
+
+use crate::catalog::catalog_version;
+use crate::error::{into_pg_error, UnknownError};
+
+type Job = Box<dyn FnOnce(&mut StatementCache<'_>) + Send>;
+
+const LOSSLESS_CONVERSION: &str = "SET arrow_lossless_conversion = true";
+
+// 未命名语句最多缓存这么多条预编译语句
+const UNNAMED_STATEMENT_CAPACITY: usize = 32;
+
+// 会话中预编译过的语句, 按 SQL 缓存. 命名语句一直保留到 Close, 未命名语句和 simple query 按 LRU 淘汰
+pub struct StatementCache<'conn> {
+    conn: &'conn Connection,
+    statements: HashMap<String, CachedStatement<'conn>>,
+    // 命名语句的名字和 SQL
+    named: HashMap<String, String>,
+    // 没有被命名语句引用的 SQL, 最近使用的在最后
+    unnamed: VecDeque<String>,
+    // 缓存里的语句是在这个 catalog 版本下 PREPARE 的
+    catalog_version: u64,
+}
+
+pub struct CachedStatement<'conn> {
+    pub statement: Statement<'conn>,
+    // 执行过一次之后得到的结果列 (ENUM 已经解析), Describe 时直接使用
+    pub fields: Option<Arc<Vec<FieldInfo>>>,
+}
+
+impl<'conn> StatementCache<'conn> {
+    fn new(conn: &'conn Connection) -> StatementCache<'conn> {
+        StatementCache {
+            conn,
+            statements: HashMap::new(),
+            named: HashMap::new(),
+            unnamed: VecDeque::new(),
+            catalog_version: catalog_version(),
+        }
+    }
+
+    pub fn connection(&self) -> &'conn Connection {
+        self.conn
+    }
+
+    // 取出缓存的预编译语句, 没有时在 DuckDB 中 PREPARE. name 为 None 表示未命名语句
+    pub fn prepare(&mut self, name: Option<&str>, query: &str) -> duckdb::Result<&mut CachedStatement<'conn>> {
+        self.check_catalog_version();
+        match name {
+            Some(name) => {
+                // 同名语句被重新 Parse 过, 旧的 SQL 不再被这个名字引用
+                if let Some(old_query) = self.named.insert(name.to_owned(), query.to_owned()) {
+                    if old_query != query {
+                        self.release(old_query);
+                    }
+                }
+                self.unnamed.retain(|unnamed| unnamed != query);
+            }
+            None if !self.is_named(query) => {
+                self.unnamed.retain(|unnamed| unnamed != query);
+                self.unnamed.push_back(query.to_owned());
+            }
+            None => {}
+        }
+        if !self.statements.contains_key(query) {
+            let statement = self.conn.prepare(query)?;
+            self.statements.insert(query.to_owned(), CachedStatement { statement, fields: None });
+        }
+        self.evict();
+        Ok(self.statements.get_mut(query).unwrap())
+    }
+
+    // Close 命名语句后, 它的预编译语句和未命名语句一样参与 LRU 淘汰
+    pub fn close(&mut self, name: &str) {
+        if let Some(query) = self.named.remove(name) {
+            self.release(query);
+        }
+    }
+
+    fn release(&mut self, query: String) {
+        if !self.is_named(&query) {
+            self.unnamed.push_back(query);
+            self.evict();
+        }
+    }
+
+    // 执行过 DDL 之后表结构可能已经变化, 缓存的预编译语句和结果列全部作废, 用到时重新 PREPARE
+    fn check_catalog_version(&mut self) {
+        let version = catalog_version();
+        if version != self.catalog_version {
+            self.statements.clear();
+            self.unnamed.clear();
+            self.catalog_version = version;
+        }
+    }
+
+    fn is_named(&self, query: &str) -> bool {
+        self.named.values().any(|named| named == query)
+    }
+
+    fn evict(&mut self) {
+        while self.unnamed.len() > UNNAMED_STATEMENT_CAPACITY {
+            if let Some(query) = self.unnamed.pop_front() {
+                self.statements.remove(&query);
+            }
+        }
+    }
+}
+
+// 每个会话独占一个工作线程执行 DuckDB 调用, 避免阻塞 tokio 的 worker 线程
 pub struct MyConnection {
-    conn: Connection
+    sender: mpsc::Sender<Job>,
 }
 
 impl MyConnection {
-    pub fn new(conn: Connection) -> MyConnection {
+    pub fn new(conn: Connection) -> PgWireResult<MyConnection> {
+        // JSON, BIT, UUID 等类型只有开启后才会以带 metadata 的扩展类型导出到 Arrow, 否则无法和 VARCHAR / BLOB 区分
+        conn.execute_batch(LOSSLESS_CONVERSION).map_err(|e| into_pg_error(e, LOSSLESS_CONVERSION))?;
+        let (sender, receiver) = mpsc::channel::<Job>();
+        thread::Builder::new()
+            .name("fatherduck-session".to_owned())
+            .spawn(move || {
+                let mut statements = StatementCache::new(&conn);
+                // 会话结束时 sender 被 drop, recv 返回 Err, 线程随之退出并关闭连接
+                while let Ok(job) = receiver.recv() {
+                    // 单个任务 panic 时只丢弃它的结果 channel, 调用方会收到错误, 线程继续服务后续请求
+                    let _ = panic::catch_unwind(AssertUnwindSafe(|| job(&mut statements)));
+                }
+            })?;
 
-        MyConnection {
-            conn
-        }
+        Ok(MyConnection {
+            sender
+        })
     }
 
-    pub fn get(&self) -> &Connection {
-        &self.conn
+    // 提交任务后立即返回, 任务的结果由调用方自己通过 channel 取回
+    pub fn spawn<F>(&self, f: F) -> PgWireResult<()>
+    where
+        F: FnOnce(&Connection) + Send + 'static,
+    {
+        self.spawn_cached(move |statements| f(statements.connection()))
     }
 
+    // 和 spawn 相同, 但是任务可以使用会话的预编译语句缓存
+    pub fn spawn_cached<F>(&self, f: F) -> PgWireResult<()>
+    where
+        F: FnOnce(&mut StatementCache<'_>) + Send + 'static,
+    {
+        self.sender
+            .send(Box::new(f))
+            .map_err(|_| session_worker_gone())
+    }
+
+    pub async fn run<F, R>(&self, f: F) -> PgWireResult<R>
+    where
+        F: FnOnce(&Connection) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        self.run_cached(move |statements| f(statements.connection())).await
+    }
+
+    pub async fn run_cached<F, R>(&self, f: F) -> PgWireResult<R>
+    where
+        F: FnOnce(&mut StatementCache<'_>) -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (result_sender, result_receiver) = oneshot::channel();
+        self.spawn_cached(move |statements| {
+            let _ = result_sender.send(f(statements));
+        })?;
+        result_receiver.await.map_err(|_| session_worker_gone())
+    }
+
+}
+
+pub fn session_worker_gone() -> PgWireError {
+    PgWireError::ApiError(Box::new(UnknownError::UnknownError(
+        "session worker thread is gone".to_owned(),
+    )))
+}
+
+// 读取 DuckDB 的 TimeZone 设置(由 ICU 扩展提供), 无法识别时按 UTC 处理
+pub fn current_time_zone(conn: &Connection) -> Tz {
+    conn.query_row("SELECT current_setting('TimeZone')", [], |row| row.get::<_, String>(0))
+        .ok()
+        .and_then(|name| name.parse::<Tz>().ok())
+        .unwrap_or(Tz::UTC)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::catalog::bump_catalog_version;
+
+    use super::*;
+
+    #[test]
+    fn test_statement_cache() {
+        let conn = Connection::open_in_memory().unwrap();
+        let mut statements = StatementCache::new(&conn);
+        statements.prepare(Some("s1"), "SELECT 1").unwrap();
+        for i in 0..=UNNAMED_STATEMENT_CAPACITY {
+            statements.prepare(None, &format!("SELECT {}", i + 2)).unwrap();
+        }
+        // 命名语句不会被淘汰, 最早的未命名语句被淘汰
+        assert!(statements.statements.contains_key("SELECT 1"));
+        assert!(!statements.statements.contains_key("SELECT 2"));
+        assert_eq!(statements.unnamed.len(), UNNAMED_STATEMENT_CAPACITY);
+
+        // Close 之后命名语句按未命名语句参与淘汰
+        statements.close("s1");
+        assert!(statements.named.is_empty());
+        assert_eq!(statements.unnamed.back().map(String::as_str), Some("SELECT 1"));
+        assert_eq!(statements.unnamed.len(), UNNAMED_STATEMENT_CAPACITY);
+    }
+
+    #[test]
+    fn test_statement_cache_invalidated_by_ddl() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("CREATE TABLE t (i INTEGER)").unwrap();
+        let mut statements = StatementCache::new(&conn);
+        statements.prepare(Some("s1"), "SELECT * FROM t").unwrap().fields = Some(Arc::new(vec![]));
+
+        conn.execute_batch("ALTER TABLE t ADD COLUMN j INTEGER").unwrap();
+        bump_catalog_version();
+        // 命名语句仍然可以使用, 但是按新的表结构重新 PREPARE
+        assert!(statements.prepare(Some("s1"), "SELECT * FROM t").unwrap().fields.is_none());
+        assert_eq!(statements.named.get("s1").map(String::as_str), Some("SELECT * FROM t"));
+    }
 }
-unsafe impl Sync for MyConnection {}
-unsafe impl Send for MyConnection {}
@@ -0,0 +1,823 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use bytes::Bytes;
+use duckdb::arrow::datatypes::SchemaRef;
+use duckdb::types::Value;
+use duckdb::{appender_params_from_iter, Connection};
+use pgwire::api::results::FieldFormat;
+use pgwire::api::Type;
+use pgwire::error::{ErrorInfo, PgWireError, PgWireResult};
+use postgres_types::Kind;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::connection::{session_worker_gone, MyConnection};
+use crate::error::{into_pg_error, unsupported_error};
+use crate::parser::{tokenize, Token, TokenKind};
+use crate::types::{array_param_literal, binary_value, bit_param, bytea_param, into_pg_field_type, json_param, text_param};
+
+// client 发送的数据先进入这个 channel, 工作线程写入较慢时 CopyData 会在这里等待
+const COPY_CHANNEL_CAPACITY: usize = 16;
+
+// 指定了部分列时先写入临时表, 再 INSERT 到目标表, 省略的列使用默认值.
+// 每次 COPY 使用不同的表名, 不会和其它 COPY 或者用户的表冲突
+const STAGING_TABLE_PREFIX: &str = "fatherduck_copy_staging";
+static STAGING_TABLE_ID: AtomicU64 = AtomicU64::new(0);
+
+const BINARY_SIGNATURE: &[u8] = b"PGCOPY\n\xff\r\n\0";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CopyFormat {
+    Text,
+    Csv,
+    Binary,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CopyOptions {
+    pub format: CopyFormat,
+    pub delimiter: u8,
+    pub null: String,
+    pub header: bool,
+    pub quote: u8,
+    pub escape: u8,
+}
+
+impl CopyOptions {
+    // 和 PostgreSQL 相同的默认值: 文本格式用 tab 分隔, NULL 为 \N; CSV 用逗号分隔, NULL 为不带引号的空字符串
+    fn new(format: CopyFormat) -> CopyOptions {
+        CopyOptions {
+            format,
+            delimiter: if format == CopyFormat::Csv { b',' } else { b'\t' },
+            null: if format == CopyFormat::Csv { String::new() } else { "\\N".to_owned() },
+            header: false,
+            quote: b'"',
+            escape: b'"',
+        }
+    }
+
+    // WITH (...) 和旧语法里的选项都先转换成 (名字, 值) 再统一处理
+    fn from_options(options: Vec<(String, Option<String>)>) -> PgWireResult<CopyOptions> {
+        let format = match options.iter().find(|(name, _)| name == "FORMAT") {
+            Some((_, value)) => match value.as_deref().map(str::to_ascii_lowercase).as_deref() {
+                Some("text") => CopyFormat::Text,
+                Some("csv") => CopyFormat::Csv,
+                Some("binary") => CopyFormat::Binary,
+                _ => return Err(unsupported_error(format!("COPY format {} not recognized", value.as_deref().unwrap_or("")))),
+            },
+            None => CopyFormat::Text,
+        };
+        let mut copy_options = CopyOptions::new(format);
+        let mut escape = None;
+        for (name, value) in options {
+            match name.as_str() {
+                "FORMAT" => {}
+                "DELIMITER" | "DELIM" | "SEP" => copy_options.delimiter = single_byte_option(&name, value)?,
+                "NULL" | "NULLSTR" => copy_options.null = value.unwrap_or_default(),
+                "HEADER" => copy_options.header = boolean_option(&name, value)?,
+                "QUOTE" => copy_options.quote = single_byte_option(&name, value)?,
+                "ESCAPE" => escape = Some(single_byte_option(&name, value)?),
+                "ENCODING" if value.as_deref().is_some_and(|encoding| {
+                    matches!(encoding.to_ascii_uppercase().replace('-', "").as_str(), "UTF8" | "UNICODE")
+                }) => {}
+                "FREEZE" => {}
+                _ => return Err(unsupported_error(format!("COPY option {} is not supported", name))),
+            }
+        }
+        // ESCAPE 默认和 QUOTE 相同
+        copy_options.escape = escape.unwrap_or(copy_options.quote);
+        if copy_options.format == CopyFormat::Binary && copy_options.header {
+            return Err(unsupported_error("cannot specify HEADER in BINARY mode".to_owned()));
+        }
+        Ok(copy_options)
+    }
+
+    pub fn field_format(&self) -> FieldFormat {
+        match self.format {
+            CopyFormat::Binary => FieldFormat::Binary,
+            CopyFormat::Text | CopyFormat::Csv => FieldFormat::Text,
+        }
+    }
+}
+
+fn single_byte_option(name: &str, value: Option<String>) -> PgWireResult<u8> {
+    match value.as_deref().map(str::as_bytes) {
+        Some([byte]) if byte.is_ascii() => Ok(*byte),
+        _ => Err(unsupported_error(format!("COPY {} must be a single one-byte character", name.to_ascii_lowercase()))),
+    }
+}
+
+fn boolean_option(name: &str, value: Option<String>) -> PgWireResult<bool> {
+    match value.as_deref().map(str::to_ascii_lowercase).as_deref() {
+        None | Some("true" | "on" | "1" | "match") => Ok(true),
+        Some("false" | "off" | "0") => Ok(false),
+        Some(value) => Err(unsupported_error(format!("{} requires a Boolean value, got {}", name.to_ascii_lowercase(), value))),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CopyStatement {
+    // 表名的各部分, 已经去掉引号
+    pub table: Vec<String>,
+    pub columns: Vec<String>,
+    pub options: CopyOptions,
+}
+
+// 只处理 COPY ... FROM STDIN, 其余 COPY 语句(读写文件)直接交给 DuckDB 执行
+pub fn parse_copy_from_stdin(sql: &str) -> Option<PgWireResult<CopyStatement>> {
+    let tokens = tokenize(sql);
+    let word = |pos: usize| match tokens.get(pos).map(|token| &token.kind) {
+        Some(TokenKind::Word(word)) => Some(word.as_str()),
+        _ => None,
+    };
+    let symbol = |pos: usize| match tokens.get(pos).map(|token| &token.kind) {
+        Some(TokenKind::Symbol(symbol)) => Some(*symbol),
+        _ => None,
+    };
+    if word(0) != Some("COPY") {
+        return None;
+    }
+
+    let mut pos = 1;
+    let mut table = vec![identifier(sql, tokens.get(pos)?)?];
+    pos += 1;
+    while symbol(pos) == Some('.') {
+        table.push(identifier(sql, tokens.get(pos + 1)?)?);
+        pos += 2;
+    }
+
+    let mut columns = vec![];
+    if symbol(pos) == Some('(') {
+        pos += 1;
+        loop {
+            columns.push(identifier(sql, tokens.get(pos)?)?);
+            pos += 1;
+            match symbol(pos) {
+                Some(',') => pos += 1,
+                Some(')') => break,
+                _ => return None,
+            }
+        }
+        pos += 1;
+    }
+
+    if word(pos) != Some("FROM") || word(pos + 1) != Some("STDIN") {
+        return None;
+    }
+    Some(parse_copy_options(sql, &tokens[pos + 2..]).map(|options| CopyStatement { table, columns, options }))
+}
+
+fn identifier(sql: &str, token: &Token) -> Option<String> {
+    let text = &sql[token.start..token.end];
+    match token.kind {
+        TokenKind::Word(_) => Some(text.to_owned()),
+        TokenKind::QuotedIdentifier => Some(text[1..text.len() - 1].replace("\"\"", "\"")),
+        _ => None,
+    }
+}
+
+// 选项的值: 字符串去掉引号, E'' 字符串处理反斜杠转义, 其余按原样
+fn option_value(sql: &str, token: &Token) -> String {
+    let text = &sql[token.start..token.end];
+    if token.kind != TokenKind::String {
+        return text.to_owned();
+    }
+    if let Some(escaped) = text.strip_prefix(['e', 'E']) {
+        let escaped = &escaped[1..escaped.len() - 1];
+        let mut value = String::new();
+        let mut chars = escaped.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => match chars.next() {
+                    Some('t') => value.push('\t'),
+                    Some('n') => value.push('\n'),
+                    Some('r') => value.push('\r'),
+                    Some(other) => value.push(other),
+                    None => {}
+                },
+                // '' 转义的单引号
+                '\'' => {
+                    chars.next();
+                    value.push('\'');
+                }
+                _ => value.push(c),
+            }
+        }
+        return value;
+    }
+    text[1..text.len() - 1].replace("''", "'")
+}
+
+// 支持 WITH (FORMAT csv, HEADER, ...) 和 PostgreSQL 9.0 之前的 WITH CSV HEADER DELIMITER AS ',' 两种写法
+fn parse_copy_options(sql: &str, tokens: &[Token]) -> PgWireResult<CopyOptions> {
+    let mut tokens = tokens
+        .iter()
+        .filter(|token| token.kind != TokenKind::Symbol(';'))
+        .peekable();
+    if matches!(tokens.peek().map(|token| &token.kind), Some(TokenKind::Word(word)) if word == "WITH") {
+        tokens.next();
+    }
+    let syntax_error = || unsupported_error("syntax error in COPY options".to_owned());
+
+    let mut options = vec![];
+    if tokens.peek().map(|token| &token.kind) == Some(&TokenKind::Symbol('(')) {
+        tokens.next();
+        loop {
+            let name = match tokens.next().map(|token| &token.kind) {
+                Some(TokenKind::Word(name)) => name.clone(),
+                _ => return Err(syntax_error()),
+            };
+            let value = match tokens.peek().map(|token| token.kind.clone()) {
+                Some(TokenKind::Symbol(',' | ')')) => None,
+                Some(_) => tokens.next().map(|token| option_value(sql, token)),
+                None => return Err(syntax_error()),
+            };
+            options.push((name, value));
+            match tokens.next().map(|token| &token.kind) {
+                Some(TokenKind::Symbol(',')) => {}
+                Some(TokenKind::Symbol(')')) => break,
+                _ => return Err(syntax_error()),
+            }
+        }
+        return match tokens.next() {
+            Some(_) => Err(syntax_error()),
+            None => CopyOptions::from_options(options),
+        };
+    }
+
+    while let Some(token) = tokens.next() {
+        let name = match &token.kind {
+            TokenKind::Word(name) => name.as_str(),
+            _ => return Err(syntax_error()),
+        };
+        match name {
+            "BINARY" | "CSV" => options.push(("FORMAT".to_owned(), Some(name.to_owned()))),
+            "HEADER" | "FREEZE" => options.push((name.to_owned(), None)),
+            "DELIMITER" | "NULL" | "QUOTE" | "ESCAPE" | "ENCODING" => {
+                if matches!(tokens.peek().map(|token| &token.kind), Some(TokenKind::Word(word)) if word == "AS") {
+                    tokens.next();
+                }
+                let value = tokens.next().ok_or_else(syntax_error)?;
+                options.push((name.to_owned(), Some(option_value(sql, value))));
+            }
+            _ => return Err(unsupported_error(format!("COPY option {} is not supported", name))),
+        }
+    }
+    CopyOptions::from_options(options)
+}
+
+// 一行数据, 每一列是文本或二进制格式的原始值
+type CopyRow = Vec<Option<Vec<u8>>>;
+
+enum CopyRecord {
+    Row(CopyRow),
+    // 二进制格式的文件头
+    Header,
+    // 文本格式的 \. 或者二进制格式的结尾
+    End,
+}
+
+// CopyData 的边界和行的边界无关, 不完整的行留在 buffer 中等待后面的数据
+pub struct CopyDecoder {
+    options: CopyOptions,
+    buffer: Vec<u8>,
+    // 已经跳过标题行或者读完了二进制格式的文件头
+    header_done: bool,
+    // 读到结束标记之后忽略剩下的数据
+    finished: bool,
+}
+
+impl CopyDecoder {
+    pub fn new(options: CopyOptions) -> CopyDecoder {
+        CopyDecoder {
+            header_done: !options.header && options.format != CopyFormat::Binary,
+            options,
+            buffer: Vec::new(),
+            finished: false,
+        }
+    }
+
+    // eof 为 true 表示 client 已经发送了 CopyDone, 最后一行可以没有换行
+    pub fn decode(&mut self, data: &[u8], eof: bool) -> PgWireResult<Vec<CopyRow>> {
+        self.buffer.extend_from_slice(data);
+        let mut rows = vec![];
+        let mut pos = 0;
+        while !self.finished && pos < self.buffer.len() {
+            let input = &self.buffer[pos..];
+            let record = match self.options.format {
+                CopyFormat::Text => next_text_record(input, eof, &self.options),
+                CopyFormat::Csv => next_csv_record(input, eof, &self.options)?,
+                CopyFormat::Binary => next_binary_record(input, self.header_done)?,
+            };
+            let Some((record, consumed)) = record else {
+                break;
+            };
+            pos += consumed;
+            match record {
+                CopyRecord::Row(row) if self.header_done => rows.push(row),
+                CopyRecord::Row(_) | CopyRecord::Header => self.header_done = true,
+                CopyRecord::End => self.finished = true,
+            }
+        }
+        if self.finished {
+            self.buffer.clear();
+        } else {
+            self.buffer.drain(..pos);
+        }
+        if eof && !self.finished && !self.buffer.is_empty() {
+            return Err(bad_copy_format(match self.options.format {
+                CopyFormat::Csv => "unterminated CSV quoted field",
+                _ => "unexpected EOF in COPY data",
+            }));
+        }
+        Ok(rows)
+    }
+}
+
+fn bad_copy_format(message: &str) -> PgWireError {
+    PgWireError::UserError(Box::new(ErrorInfo::new("ERROR".to_owned(), "22P04".to_owned(), message.to_owned())))
+}
+
+// 文本格式一行一条记录, 列之间用分隔符分开, 特殊字符用反斜杠转义
+fn next_text_record(input: &[u8], eof: bool, options: &CopyOptions) -> Option<(CopyRecord, usize)> {
+    let (line, consumed) = match input.iter().position(|&b| b == b'\n') {
+        Some(end) => (&input[..end], end + 1),
+        None if eof => (input, input.len()),
+        None => return None,
+    };
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    if line == b"\\." {
+        return Some((CopyRecord::End, consumed));
+    }
+
+    let mut row = vec![];
+    let mut start = 0;
+    let mut i = 0;
+    while i <= line.len() {
+        if i == line.len() || line[i] == options.delimiter {
+            let raw = &line[start..i];
+            row.push((raw != options.null.as_bytes()).then(|| unescape_text(raw)));
+            start = i + 1;
+        } else if line[i] == b'\\' && i + 1 < line.len() {
+            // 转义的分隔符不结束当前列
+            i += 1;
+        }
+        i += 1;
+    }
+    Some((CopyRecord::Row(row), consumed))
+}
+
+fn unescape_text(raw: &[u8]) -> Vec<u8> {
+    let mut value = Vec::with_capacity(raw.len());
+    let mut i = 0;
+    while i < raw.len() {
+        if raw[i] != b'\\' || i + 1 == raw.len() {
+            value.push(raw[i]);
+            i += 1;
+            continue;
+        }
+        let c = raw[i + 1];
+        i += 2;
+        match c {
+            b'b' => value.push(0x08),
+            b'f' => value.push(0x0c),
+            b'n' => value.push(b'\n'),
+            b'r' => value.push(b'\r'),
+            b't' => value.push(b'\t'),
+            b'v' => value.push(0x0b),
+            b'0'..=b'7' => {
+                let mut byte = (c - b'0') as u32;
+                for _ in 0..2 {
+                    match raw.get(i) {
+                        Some(digit @ b'0'..=b'7') => {
+                            byte = byte * 8 + (digit - b'0') as u32;
+                            i += 1;
+                        }
+                        _ => break,
+                    }
+                }
+                value.push(byte as u8);
+            }
+            b'x' if raw.get(i).is_some_and(u8::is_ascii_hexdigit) => {
+                let end = if raw.get(i + 1).is_some_and(u8::is_ascii_hexdigit) { i + 2 } else { i + 1 };
+                let hex = std::str::from_utf8(&raw[i..end]).unwrap_or("0");
+                value.push(u8::from_str_radix(hex, 16).unwrap_or(0));
+                i = end;
+            }
+            other => value.push(other),
+        }
+    }
+    value
+}
+
+// CSV 的引号内可以包含分隔符和换行, 一条记录可能跨越多个 CopyData
+fn next_csv_record(input: &[u8], eof: bool, options: &CopyOptions) -> PgWireResult<Option<(CopyRecord, usize)>> {
+    let mut row = vec![];
+    let mut value = vec![];
+    let mut quoted = false;
+    let mut in_quote = false;
+    let mut i = 0;
+    let consumed = loop {
+        let Some(&c) = input.get(i) else {
+            if !eof || in_quote {
+                return Ok(None);
+            }
+            break i;
+        };
+        if in_quote {
+            let next = input.get(i + 1).copied();
+            if c == options.escape && next == Some(options.quote) {
+                value.push(options.quote);
+                i += 2;
+                continue;
+            }
+            if c == options.quote {
+                // 引号在数据末尾时需要看到下一个字节才能判断是不是转义
+                if next.is_none() && options.escape == options.quote && !eof {
+                    return Ok(None);
+                }
+                in_quote = false;
+            } else {
+                value.push(c);
+            }
+            i += 1;
+            continue;
+        }
+        match c {
+            b'\n' => break i + 1,
+            b'\r' if input.get(i + 1) == Some(&b'\n') => break i + 2,
+            // \r 在数据末尾时还不知道后面是不是 \n
+            b'\r' if i + 1 == input.len() && !eof => return Ok(None),
+            c if c == options.delimiter => {
+                row.push(csv_value(&mut value, quoted, options));
+                quoted = false;
+            }
+            c if c == options.quote => {
+                quoted = true;
+                in_quote = true;
+            }
+            _ => value.push(c),
+        }
+        i += 1;
+    };
+    if row.is_empty() && !quoted && value == b"\\." {
+        return Ok(Some((CopyRecord::End, consumed)));
+    }
+    row.push(csv_value(&mut value, quoted, options));
+    Ok(Some((CopyRecord::Row(row), consumed)))
+}
+
+// 只有不带引号且和 NULL 字符串相同的值才是 NULL, "" 是空字符串
+fn csv_value(value: &mut Vec<u8>, quoted: bool, options: &CopyOptions) -> Option<Vec<u8>> {
+    let value = std::mem::take(value);
+    (quoted || value != options.null.as_bytes()).then_some(value)
+}
+
+// 二进制格式: 文件头之后每行是 i16 列数, 每列是 i32 长度(-1 表示 NULL)加数据, 最后以列数 -1 结束
+fn next_binary_record(input: &[u8], header_done: bool) -> PgWireResult<Option<(CopyRecord, usize)>> {
+    if !header_done {
+        let fixed = BINARY_SIGNATURE.len() + 8;
+        if input.len() < fixed {
+            return Ok(None);
+        }
+        if !input.starts_with(BINARY_SIGNATURE) {
+            return Err(bad_copy_format("COPY file signature not recognized"));
+        }
+        let flags = read_i32(&input[BINARY_SIGNATURE.len()..]);
+        if flags & (1 << 16) != 0 {
+            return Err(unsupported_error("COPY with OIDs is not supported".to_owned()));
+        }
+        let extension = read_i32(&input[BINARY_SIGNATURE.len() + 4..]).max(0) as usize;
+        return Ok((input.len() >= fixed + extension).then_some((CopyRecord::Header, fixed + extension)));
+    }
+
+    if input.len() < 2 {
+        return Ok(None);
+    }
+    let count = i16::from_be_bytes([input[0], input[1]]);
+    if count == -1 {
+        return Ok(Some((CopyRecord::End, 2)));
+    }
+    let mut row = Vec::with_capacity(count.max(0) as usize);
+    let mut pos = 2;
+    for _ in 0..count {
+        if input.len() < pos + 4 {
+            return Ok(None);
+        }
+        let len = read_i32(&input[pos..]);
+        pos += 4;
+        if len < 0 {
+            row.push(None);
+            continue;
+        }
+        let end = pos + len as usize;
+        if input.len() < end {
+            return Ok(None);
+        }
+        row.push(Some(input[pos..end].to_vec()));
+        pos = end;
+    }
+    Ok(Some((CopyRecord::Row(row), pos)))
+}
+
+fn read_i32(input: &[u8]) -> i32 {
+    i32::from_be_bytes([input[0], input[1], input[2], input[3]])
+}
+
+pub enum CopyMessage {
+    Data(Bytes),
+    Done,
+}
+
+// 正在进行的 COPY FROM STDIN, 数据由工作线程上的任务写入 DuckDB.
+// 丢弃 sender 而没有发送 Done 表示 client 放弃了 COPY, 已经写入的数据会被回滚
+pub struct CopyIn {
+    pub sender: mpsc::Sender<CopyMessage>,
+    pub result: oneshot::Receiver<PgWireResult<usize>>,
+}
+
+// 在工作线程上开始 COPY, 返回目标列数. 之后工作线程一直处理这次 COPY, 直到收到 Done 或者 sender 被丢弃
+pub async fn spawn_copy_in(
+    conn: &MyConnection,
+    statement: CopyStatement,
+    implicit_transaction: bool,
+) -> PgWireResult<(CopyIn, usize)> {
+    let (ready_sender, ready_receiver) = oneshot::channel();
+    let (data_sender, data_receiver) = mpsc::channel(COPY_CHANNEL_CAPACITY);
+    let (result_sender, result_receiver) = oneshot::channel();
+    conn.spawn(move |conn| {
+        let target = match CopyTarget::new(conn, &statement) {
+            Ok(target) => target,
+            Err(e) => {
+                let _ = ready_sender.send(Err(e));
+                return;
+            }
+        };
+        if ready_sender.send(Ok(target.types.len())).is_err() {
+            return;
+        }
+        let _ = result_sender.send(target.copy_in(conn, &statement.options, implicit_transaction, data_receiver));
+    })?;
+    let columns = ready_receiver.await.map_err(|_| session_worker_gone())??;
+    Ok((CopyIn { sender: data_sender, result: result_receiver }, columns))
+}
+
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+fn query_schema(conn: &Connection, query: &str) -> PgWireResult<SchemaRef> {
+    let mut stmt = conn.prepare(query).map_err(|e| into_pg_error(e, query))?;
+    // 语句执行之后才能拿到 schema
+    stmt.execute([]).map_err(|e| into_pg_error(e, query))?;
+    Ok(stmt.schema())
+}
+
+struct CopyTarget {
+    // 带引号的表名
+    table: String,
+    schema: Option<String>,
+    name: String,
+    // 带引号的列名, 用逗号连接
+    columns: String,
+    types: Vec<Type>,
+    // 不能直接用 Appender 写入目标表(只写入部分列, 或者表名带有数据库名)时使用的临时表
+    staging: Option<String>,
+}
+
+impl CopyTarget {
+    fn new(conn: &Connection, statement: &CopyStatement) -> PgWireResult<CopyTarget> {
+        let table = statement.table.iter().map(|part| quote_identifier(part)).collect::<Vec<_>>().join(".");
+        let table_schema = query_schema(conn, &format!("SELECT * FROM {} LIMIT 0", table))?;
+        let (columns, schema) = if statement.columns.is_empty() {
+            let columns = table_schema.fields().iter().map(|field| quote_identifier(field.name())).collect::<Vec<_>>();
+            (columns.join(", "), table_schema.clone())
+        } else {
+            let columns = statement.columns.iter().map(|column| quote_identifier(column)).collect::<Vec<_>>().join(", ");
+            let schema = query_schema(conn, &format!("SELECT {} FROM {} LIMIT 0", columns, table))?;
+            (columns, schema)
+        };
+        let all_columns = schema.fields().len() == table_schema.fields().len()
+            && schema
+                .fields()
+                .iter()
+                .zip(table_schema.fields().iter())
+                .all(|(column, table_column)| column.name().eq_ignore_ascii_case(table_column.name()));
+        let types = schema
+            .fields()
+            .iter()
+            .map(|field| into_pg_field_type(field))
+            .collect::<PgWireResult<Vec<Type>>>()?;
+        let (schema_name, name) = match statement.table.as_slice() {
+            [name] => (None, name.clone()),
+            [schema, name] => (Some(schema.clone()), name.clone()),
+            _ => (None, String::new()),
+        };
+        Ok(CopyTarget {
+            table,
+            schema: schema_name,
+            name,
+            columns,
+            types,
+            staging: (!all_columns || statement.table.len() > 2).then(|| {
+                format!("{}_{}", STAGING_TABLE_PREFIX, STAGING_TABLE_ID.fetch_add(1, Ordering::Relaxed))
+            }),
+        })
+    }
+
+    // 没有处于事务中时, 整个 COPY 在一个事务中执行, 出错或者被 client 放弃时不会留下部分数据
+    fn copy_in(
+        &self,
+        conn: &Connection,
+        options: &CopyOptions,
+        implicit_transaction: bool,
+        data: mpsc::Receiver<CopyMessage>,
+    ) -> PgWireResult<usize> {
+        if implicit_transaction {
+            conn.execute_batch("BEGIN TRANSACTION").map_err(|e| into_pg_error(e, "BEGIN TRANSACTION"))?;
+        }
+        let result = self.append_rows(conn, options, data);
+        if implicit_transaction {
+            let end = if result.is_ok() { "COMMIT" } else { "ROLLBACK" };
+            let ended = conn.execute_batch(end).map_err(|e| into_pg_error(e, end));
+            if result.is_ok() {
+                ended?;
+            }
+        }
+        result
+    }
+
+    fn append_rows(&self, conn: &Connection, options: &CopyOptions, mut data: mpsc::Receiver<CopyMessage>) -> PgWireResult<usize> {
+        let appender = if let Some(staging) = &self.staging {
+            let create = format!(
+                "CREATE TEMP TABLE {} AS SELECT {} FROM {} LIMIT 0",
+                staging, self.columns, self.table
+            );
+            conn.execute_batch(&create).map_err(|e| into_pg_error(e, &create))?;
+            conn.appender_to_db(staging, "main")
+        } else {
+            let schema = match &self.schema {
+                Some(schema) => schema.clone(),
+                None => conn
+                    .query_row("SELECT current_schema()", [], |row| row.get::<_, String>(0))
+                    .map_err(|e| into_pg_error(e, "SELECT current_schema()"))?,
+            };
+            conn.appender_to_db(&self.name, &schema)
+        };
+        let mut appender = appender.map_err(|e| into_pg_error(e, ""))?;
+
+        let format = options.field_format();
+        let mut decoder = CopyDecoder::new(options.clone());
+        let mut rows = 0;
+        loop {
+            let (chunk, eof) = match data.blocking_recv() {
+                Some(CopyMessage::Data(chunk)) => (chunk, false),
+                Some(CopyMessage::Done) => (Bytes::new(), true),
+                None => return Err(copy_aborted()),
+            };
+            for row in decoder.decode(&chunk, eof)? {
+                let values = self.row_values(row, format)?;
+                appender.append_row(appender_params_from_iter(values)).map_err(|e| into_pg_error(e, ""))?;
+                rows += 1;
+            }
+            if eof {
+                break;
+            }
+        }
+        appender.flush().map_err(|e| into_pg_error(e, ""))?;
+        drop(appender);
+
+        if let Some(staging) = &self.staging {
+            let insert = format!("INSERT INTO {} ({}) SELECT * FROM {}", self.table, self.columns, staging);
+            conn.execute_batch(&insert).map_err(|e| into_pg_error(e, &insert))?;
+            let drop_staging = format!("DROP TABLE {}", staging);
+            conn.execute_batch(&drop_staging).map_err(|e| into_pg_error(e, &drop_staging))?;
+        }
+        Ok(rows)
+    }
+
+    fn row_values(&self, row: CopyRow, format: FieldFormat) -> PgWireResult<Vec<Value>> {
+        if row.len() > self.types.len() {
+            return Err(bad_copy_format("extra data after last expected column"));
+        }
+        if row.len() < self.types.len() {
+            return Err(bad_copy_format("missing data for column"));
+        }
+        row.into_iter()
+            .zip(self.types.iter())
+            .map(|(raw, column_type)| copy_value(raw, format, column_type))
+            .collect()
+    }
+}
+
+// 二进制格式的标量值按列的类型解码, 其它值和参数一样转换成 PostgreSQL 的文本格式, 由 DuckDB 转换成列的实际类型
+fn copy_value(raw: Option<Vec<u8>>, format: FieldFormat, column_type: &Type) -> PgWireResult<Value> {
+    let Some(raw) = raw else {
+        return Ok(Value::Null);
+    };
+    match (column_type, column_type.kind()) {
+        (&Type::BYTEA, _) => bytea_param(&raw, format).map(Value::Blob),
+        (&Type::BIT | &Type::VARBIT, _) => bit_param(&raw, format, column_type).map(Value::Text),
+        (&Type::JSON | &Type::JSONB, _) => json_param(&raw, format, column_type).map(Value::Text),
+        (_, Kind::Array(_)) => array_param_literal(&raw, format, column_type).map(Value::Text),
+        _ if format == FieldFormat::Binary => binary_value(&raw, column_type),
+        _ => text_param(&raw, format, column_type).map(Value::Text),
+    }
+}
+
+pub fn copy_aborted() -> PgWireError {
+    PgWireError::UserError(Box::new(ErrorInfo::new(
+        "ERROR".to_owned(),
+        "57014".to_owned(),
+        "COPY from stdin failed".to_owned(),
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(value: &str) -> Option<Vec<u8>> {
+        Some(value.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn test_parse_copy_from_stdin() {
+        let statement = parse_copy_from_stdin("COPY main.\"T\" (a, \"B\") FROM STDIN WITH (FORMAT csv, HEADER, DELIMITER ';')")
+            .unwrap()
+            .unwrap();
+        assert_eq!(statement.table, vec!["main", "T"]);
+        assert_eq!(statement.columns, vec!["a", "B"]);
+        assert_eq!(statement.options.format, CopyFormat::Csv);
+        assert_eq!(statement.options.delimiter, b';');
+        assert!(statement.options.header);
+        assert_eq!(statement.options.null, "");
+
+        let statement = parse_copy_from_stdin("copy t from stdin with csv header delimiter as E'\\t' null 'NA';").unwrap().unwrap();
+        assert_eq!(statement.options.format, CopyFormat::Csv);
+        assert_eq!(statement.options.delimiter, b'\t');
+        assert_eq!(statement.options.null, "NA");
+
+        let statement = parse_copy_from_stdin("COPY t FROM STDIN").unwrap().unwrap();
+        assert_eq!(statement.options, CopyOptions::new(CopyFormat::Text));
+        assert_eq!(parse_copy_from_stdin("COPY t FROM STDIN BINARY").unwrap().unwrap().options.format, CopyFormat::Binary);
+
+        assert!(parse_copy_from_stdin("COPY t FROM 'data.csv'").is_none());
+        assert!(parse_copy_from_stdin("COPY t TO STDOUT").is_none());
+        assert!(parse_copy_from_stdin("COPY t FROM STDIN (FORMAT xml)").unwrap().is_err());
+        assert!(parse_copy_from_stdin("COPY t FROM STDIN (DELIMITER ';;')").unwrap().is_err());
+    }
+
+    #[test]
+    fn test_decode_text() {
+        let mut decoder = CopyDecoder::new(CopyOptions::new(CopyFormat::Text));
+        assert_eq!(decoder.decode(b"1\ta\\tb\n2\t\\N\n3\t", false).unwrap(), vec![
+            vec![text("1"), text("a\tb")],
+            vec![text("2"), None],
+        ]);
+        // 行被拆分在两个 CopyData 中
+        assert_eq!(decoder.decode(b"c\\\\d\\101\n\\.\nignored\n", false).unwrap(), vec![vec![text("3"), text("c\\dA")]]);
+        assert!(decoder.decode(b"", true).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_decode_csv() {
+        let mut options = CopyOptions::new(CopyFormat::Csv);
+        options.header = true;
+        let mut decoder = CopyDecoder::new(options);
+        assert!(decoder.decode(b"id,name\r\n1,\"a,\"\"b\"\"\n", false).unwrap().is_empty());
+        assert_eq!(decoder.decode(b"c\"\r\n2,\n3,\"\"", false).unwrap(), vec![
+            vec![text("1"), text("a,\"b\"\nc")],
+            vec![text("2"), None],
+        ]);
+        assert_eq!(decoder.decode(b"", true).unwrap(), vec![vec![text("3"), text("")]]);
+
+        let mut decoder = CopyDecoder::new(CopyOptions::new(CopyFormat::Csv));
+        assert!(decoder.decode(b"1,\"abc", true).is_err());
+    }
+
+    #[test]
+    fn test_decode_binary() {
+        let mut data = BINARY_SIGNATURE.to_vec();
+        data.extend_from_slice(&0i32.to_be_bytes());
+        data.extend_from_slice(&0i32.to_be_bytes());
+        data.extend_from_slice(&2i16.to_be_bytes());
+        data.extend_from_slice(&4i32.to_be_bytes());
+        data.extend_from_slice(&7i32.to_be_bytes());
+        data.extend_from_slice(&(-1i32).to_be_bytes());
+        data.extend_from_slice(&(-1i16).to_be_bytes());
+
+        let mut decoder = CopyDecoder::new(CopyOptions::new(CopyFormat::Binary));
+        let (first, second) = data.split_at(20);
+        assert!(decoder.decode(first, false).unwrap().is_empty());
+        assert_eq!(decoder.decode(second, false).unwrap(), vec![vec![Some(7i32.to_be_bytes().to_vec()), None]]);
+        assert!(decoder.decode(b"", true).unwrap().is_empty());
+
+        let value = copy_value(Some(7i32.to_be_bytes().to_vec()), FieldFormat::Binary, &Type::INT4).unwrap();
+        assert_eq!(value, Value::Int(7));
+        // 2000-01-02 是 PostgreSQL 纪元之后的第 1 天
+        let value = copy_value(Some(1i32.to_be_bytes().to_vec()), FieldFormat::Binary, &Type::DATE).unwrap();
+        assert_eq!(value, Value::Date32(10958));
+        let value = copy_value(Some(0.5f64.to_be_bytes().to_vec()), FieldFormat::Binary, &Type::FLOAT8).unwrap();
+        assert_eq!(value, Value::Double(0.5));
+    }
+}
@@ -2,7 +2,9 @@ use derive_new::new;
 
 use thiserror::Error;
 use pgwire::api::{ClientInfo, ErrorHandler};
-use pgwire::error::PgWireError;
+use pgwire::error::{ErrorInfo, PgWireError};
+
+use crate::parser::SourceMap;
 
 #[derive(Error, Debug)]
 pub enum UnknownError {
@@ -12,6 +14,208 @@ pub enum UnknownError {
 }
 
 
+// 暂不支持的类型或参数, 作为错误返回给 client 而不是 panic
+pub fn unsupported_error(message: String) -> PgWireError {
+    PgWireError::UserError(Box::new(ErrorInfo::new("ERROR".to_owned(), "0A000".to_owned(), message)))
+}
+
+// 无法编码或解码的值
+pub fn invalid_value_error(message: String) -> PgWireError {
+    PgWireError::UserError(Box::new(ErrorInfo::new("ERROR".to_owned(), "22000".to_owned(), message)))
+}
+
+// 把 DuckDB 的错误转换成带 SQLSTATE 的 PostgreSQL 错误, query 是交给 DuckDB 执行的语句, 用于计算出错位置
+pub fn into_pg_error(error: duckdb::Error, query: &str) -> PgWireError {
+    let error_info = match &error {
+        duckdb::Error::DuckDBFailure(_, Some(message)) => parse_duckdb_error(message, query),
+        duckdb::Error::FromSqlConversionFailure(..)
+        | duckdb::Error::IntegralValueOutOfRange(..)
+        | duckdb::Error::InvalidColumnType(..) => {
+            ErrorInfo::new("ERROR".to_owned(), "22000".to_owned(), error.to_string())
+        }
+        duckdb::Error::InvalidParameterCount(..) => {
+            ErrorInfo::new("ERROR".to_owned(), "08P01".to_owned(), error.to_string())
+        }
+        _ => ErrorInfo::new("ERROR".to_owned(), "XX000".to_owned(), error.to_string()),
+    };
+    PgWireError::UserError(Box::new(error_info))
+}
+
+// 出错位置是按改写后交给 DuckDB 的语句计算的, 换算成 client 发送的原文中的位置, 换算不了时不报告位置
+pub fn map_error_position(error: PgWireError, rewritten: &str, original: &str, source_map: &SourceMap) -> PgWireError {
+    match error {
+        PgWireError::UserError(mut error_info) => {
+            error_info.position = error_info
+                .position
+                .as_deref()
+                .and_then(|position| position.parse::<usize>().ok())
+                .and_then(|position| source_map.original_position(rewritten, original, position))
+                .map(|position| position.to_string());
+            PgWireError::UserError(error_info)
+        }
+        other => other,
+    }
+}
+
+// DuckDB 的错误信息形如:
+// Catalog Error: Table with name foo does not exist!
+// Did you mean "bar"?
+//
+// LINE 1: SELECT * FROM foo
+//                       ^
+fn parse_duckdb_error(message: &str, query: &str) -> ErrorInfo {
+    let (body, context) = match message.find("\n\nLINE ") {
+        Some(idx) => (&message[..idx], Some(&message[idx + 2..])),
+        None => (message, None),
+    };
+    let (error_type, body) = match body.split_once(" Error: ") {
+        Some((error_type, body)) if !error_type.contains('\n') => (error_type, body),
+        _ => ("", body),
+    };
+
+    let mut lines = body.lines();
+    let primary = lines.next().unwrap_or("").to_owned();
+    let (hints, details): (Vec<&str>, Vec<&str>) = lines
+        .filter(|line| !line.trim().is_empty())
+        .partition(|line| line.starts_with("Did you mean") || line.starts_with("HINT: "));
+
+    let severity = if error_type == "FATAL" { "FATAL" } else { "ERROR" };
+    let mut error_info = ErrorInfo::new(severity.to_owned(), sqlstate(error_type, body).to_owned(), primary);
+    if !details.is_empty() {
+        error_info.detail = Some(details.join("\n"));
+    }
+    if !hints.is_empty() {
+        error_info.hint = Some(hints.iter().map(|hint| hint.trim_start_matches("HINT: ")).collect::<Vec<_>>().join("\n"));
+    }
+    error_info.position = context
+        .and_then(|context| error_position(context, query))
+        .map(|position| position.to_string());
+    error_info
+}
+
+// https://www.postgresql.org/docs/current/errcodes-appendix.html
+fn sqlstate(error_type: &str, message: &str) -> &'static str {
+    let message = message.to_lowercase();
+    match error_type {
+        "Parser" | "Syntax" => "42601",
+        "Catalog" => {
+            if message.contains("already exists") {
+                if message.starts_with("table") || message.starts_with("view") {
+                    "42P07"
+                } else if message.starts_with("schema") {
+                    "42P06"
+                } else {
+                    "42710"
+                }
+            } else if message.starts_with("table") || message.starts_with("view") {
+                "42P01"
+            } else if message.starts_with("schema") {
+                "3F000"
+            } else if message.contains("function") || message.contains("macro") {
+                "42883"
+            } else {
+                "42704"
+            }
+        }
+        "Binder" => {
+            if message.contains("column") && (message.contains("not found") || message.contains("does not have a column")) {
+                "42703"
+            } else if message.contains("ambiguous") {
+                "42702"
+            } else if message.contains("no function matches") || (message.contains("function") && message.contains("does not exist")) {
+                "42883"
+            } else if message.contains("group by") || message.contains("aggregate") {
+                "42803"
+            } else if message.contains("table") && message.contains("does not exist") {
+                "42P01"
+            } else {
+                "42000"
+            }
+        }
+        "Constraint" => {
+            if message.contains("duplicate key") || message.contains("unique") || message.contains("primary key") {
+                "23505"
+            } else if message.contains("not null") {
+                "23502"
+            } else if message.contains("foreign key") {
+                "23503"
+            } else if message.contains("check constraint") {
+                "23514"
+            } else {
+                "23000"
+            }
+        }
+        "Conversion" | "Invalid Input" => {
+            if message.contains("out of range") || message.contains("overflow") {
+                "22003"
+            } else if error_type == "Conversion" || message.contains("could not convert") {
+                "22P02"
+            } else {
+                "22023"
+            }
+        }
+        "Out of Range" => {
+            if message.contains("division by zero") {
+                "22012"
+            } else {
+                "22003"
+            }
+        }
+        "Divide by Zero" => "22012",
+        "Decimal" => "22003",
+        "Mismatch Type" | "Invalid type" | "Unknown Type" => "42804",
+        "Parameter Not Resolved" => "42P18",
+        "Parameter Not Allowed" => "42P02",
+        "TransactionContext" => {
+            if message.contains("aborted") {
+                "25P02"
+            } else if message.contains("within a transaction") || message.contains("already") {
+                "25001"
+            } else if message.contains("no transaction") {
+                "25P01"
+            } else if message.contains("conflict") {
+                "40001"
+            } else {
+                "25000"
+            }
+        }
+        "Serialization" => "40001",
+        "Dependency" => "2BP01",
+        "Sequence" => "2200H",
+        "Not implemented" => "0A000",
+        "Permission" => "42501",
+        "IO" | "HTTP" => "58030",
+        "Out of Memory" => "53200",
+        "INTERRUPT" => "57014",
+        "Connection" => "08000",
+        "Settings" | "Invalid Configuration" => "22023",
+        "Missing Extension" | "Auto-load" => "58P01",
+        _ => "XX000",
+    }
+}
+
+// 根据 "LINE n: ..." 和下一行的 ^ 计算出错位置, PostgreSQL 的 position 是从 1 开始的字符位置
+fn error_position(context: &str, query: &str) -> Option<usize> {
+    let mut lines = context.lines();
+    let line = lines.next()?.strip_prefix("LINE ")?;
+    let (line_number, text) = line.split_once(": ")?;
+    let caret = lines.next()?.find('^')?;
+    let column = caret.checked_sub("LINE ".len() + line_number.len() + ": ".len())?;
+    // 过长的行会被截断, 用 ... 表示
+    let (text, column) = match text.strip_prefix("...") {
+        Some(text) => (text, column.checked_sub(3)?),
+        None => (text, column),
+    };
+    let text = text.trim_end_matches("...");
+
+    let line_index = line_number.parse::<usize>().ok()?.checked_sub(1)?;
+    let line_start: usize = query.split_inclusive('\n').take(line_index).map(str::len).sum();
+    let query_line = query.split_inclusive('\n').nth(line_index)?;
+    let text_start = line_start + query_line.find(text)?;
+    let before_error = query[..text_start].chars().count() + column;
+    Some(before_error + 1)
+}
+
 #[derive(new, Debug)]
 pub struct FatherDuckErrorHandler {
 }
@@ -21,6 +225,51 @@ impl ErrorHandler for FatherDuckErrorHandler {
     where
         C: ClientInfo,
     {
-        println!("on_error: {:?}", _error);
+        log_error("on_error", _error);
+    }
+}
+
+// 没有 client 可以接收的错误(例如会话建立失败)和 on_error 一样输出
+pub fn log_error(context: &str, error: &PgWireError) {
+    println!("{}: {:?}", context, error);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_catalog_error() {
+        let query = "SELECT *\nFROM foo";
+        let message = "Catalog Error: Table with name foo does not exist!\nDid you mean \"bar\"?\n\nLINE 2: FROM foo\n             ^";
+        let error_info = parse_duckdb_error(message, query);
+        assert_eq!(error_info.code, "42P01");
+        assert_eq!(error_info.message, "Table with name foo does not exist!");
+        assert_eq!(error_info.hint.as_deref(), Some("Did you mean \"bar\"?"));
+        assert_eq!(error_info.position.as_deref(), Some("15"));
+    }
+
+    #[test]
+    fn test_map_error_position() {
+        let original = "SELECT 1; SELECT x::jsonb, y FROM pg_catalog.pg_type";
+        let statement = "SELECT x::jsonb, y FROM pg_catalog.pg_type";
+        let (rewritten, source_map) = crate::parser::rewrite_query(statement);
+        let message = "Binder Error: Referenced column \"y\" not found in FROM clause!\n\nLINE 1: SELECT x::json, y FROM pg_type\n                        ^";
+        let error = PgWireError::UserError(Box::new(parse_duckdb_error(message, &rewritten)));
+        let error = map_error_position(error, &rewritten, original, &source_map.with_offset(10));
+        let PgWireError::UserError(error_info) = error else {
+            panic!("unexpected error");
+        };
+        assert_eq!(error_info.position.as_deref(), Some("28"));
+    }
+
+    #[test]
+    fn test_sqlstate() {
+        assert_eq!(sqlstate("Parser", "syntax error at or near \"SELEC\""), "42601");
+        assert_eq!(sqlstate("Constraint", "Duplicate key \"i: 1\" violates primary key constraint."), "23505");
+        assert_eq!(sqlstate("Constraint", "NOT NULL constraint failed: t.i"), "23502");
+        assert_eq!(sqlstate("Binder", "Referenced column \"x\" not found in FROM clause!"), "42703");
+        assert_eq!(sqlstate("Out of Range", "Overflow in division of 1 / 0"), "22003");
+        assert_eq!(sqlstate("Something New", ""), "XX000");
     }
 }
@@ -5,6 +5,9 @@ mod query;
 mod error;
 mod parser;
 mod config;
+mod types;
+mod catalog;
+mod copy;
 
 use server::start_server;
 
@@ -1,16 +1,22 @@
+use std::ops::Range;
+
 use async_trait::async_trait;
 use lazy_static::lazy_static;
 use fancy_regex::Regex;
 use derive_new::new;
 use pgwire::api::stmt::QueryParser;
 use pgwire::api::Type;
-use pgwire::error::PgWireResult;
+use pgwire::error::{PgWireError, PgWireResult};
+
+use crate::error::map_error_position;
 
 
 lazy_static! {
     // 定义不可变的替换规则
     static ref QUERY_REPLACEMENTS: Vec<(Regex, &'static str)> = vec![
         (Regex::new(r"(?i)'(\w+)'::regclass").unwrap(), r"(SELECT oid FROM pg_class WHERE relname = '$1')"),
+        // pg_type 和 pg_enum 由会话的临时视图模拟, 不能带 pg_catalog 前缀
+        (Regex::new(r"(?i)\bpg_catalog\.(pg_type|pg_enum)\b").unwrap(), r"$1"),
 
         (Regex::new(r"^(?i)SHOW\s+TRANSACTION\s+ISOLATION\s+LEVEL").unwrap(), r"SELECT 'read committed' AS transaction_isolation"),
         (Regex::new(r"^(?i)SHOW\s+(?!(DATABASES|TABLES)\b)(\w+)").unwrap(), r"SELECT current_setting('$2') AS $2"),
@@ -22,15 +28,690 @@ lazy_static! {
     ];
 }
 
-pub fn rewrite_query(sql: &str) -> String {
+// 改写后的 SQL 和原文的对应关系, 用于把 DuckDB 报告的出错位置换算回 client 发送的原文
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SourceMap {
+    // 改写前的 SQL 在原文中的字节偏移: 去掉的前导空白, 以及 simple query 里前面的语句
+    offset: usize,
+    // 每一遍改写替换过的区间, 按改写的顺序
+    passes: Vec<Vec<Edit>>,
+}
+
+// 一处替换: 替换后和替换前的字节区间
+#[derive(Debug, Clone, PartialEq)]
+struct Edit {
+    output: Range<usize>,
+    input: Range<usize>,
+}
+
+impl SourceMap {
+    pub fn with_offset(mut self, offset: usize) -> SourceMap {
+        self.offset += offset;
+        self
+    }
+
+    // 改写后的字节偏移对应的原文字节偏移, 落在替换出来的文本里时对应被替换文本的开头
+    fn original_offset(&self, offset: usize) -> usize {
+        let offset = self.passes.iter().rev().fold(offset, |offset, edits| {
+            let mut shifted = offset;
+            for edit in edits {
+                if offset < edit.output.start {
+                    break;
+                }
+                if offset < edit.output.end {
+                    return edit.input.start;
+                }
+                shifted = offset - edit.output.end + edit.input.end;
+            }
+            shifted
+        });
+        self.offset + offset
+    }
+
+    // position 是改写后 SQL 中从 1 开始的字符位置, 换算成原文中的字符位置
+    pub fn original_position(&self, rewritten: &str, original: &str, position: usize) -> Option<usize> {
+        let offset = rewritten
+            .char_indices()
+            .nth(position.checked_sub(1)?)
+            .map_or(rewritten.len(), |(idx, _)| idx);
+        let offset = self.original_offset(offset);
+        Some(original.get(..offset)?.chars().count() + 1)
+    }
+}
+
+pub fn rewrite_query(sql: &str) -> (String, SourceMap) {
     let trim_sql = sql.trim();
-    let result = QUERY_REPLACEMENTS.iter().fold(trim_sql.to_string(), |acc, (re, replacement)| {
-        re.replace_all(&acc, *replacement).to_string()
-    });
-    if trim_sql != result {
-        println!("rewrite_query:\nbefore:\n{}\nafter:\n{}", sql, result);
+    let mut source_map = SourceMap { offset: sql.len() - sql.trim_start().len(), passes: vec![] };
+    let mut result = trim_sql.to_string();
+    for (re, replacement) in QUERY_REPLACEMENTS.iter() {
+        let (rewritten, edits) = replace_all(re, replacement, &result);
+        result = rewritten;
+        source_map.passes.push(edits);
+    }
+    let (result, edits) = rewrite_json(&result);
+    source_map.passes.push(edits);
+    (result, source_map)
+}
+
+// 和 Regex::replace_all 相同, 同时记录每一处替换的位置
+fn replace_all(re: &Regex, replacement: &str, sql: &str) -> (String, Vec<Edit>) {
+    let mut result = String::with_capacity(sql.len());
+    let mut edits = vec![];
+    let mut copied = 0;
+    for caps in re.captures_iter(sql).flatten() {
+        let Some(matched) = caps.get(0) else {
+            continue;
+        };
+        result.push_str(&sql[copied..matched.start()]);
+        let start = result.len();
+        caps.expand(replacement, &mut result);
+        edits.push(Edit { output: start..result.len(), input: matched.start()..matched.end() });
+        copied = matched.end();
+    }
+    result.push_str(&sql[copied..]);
+    (result, edits)
+}
+
+// PostgreSQL 的 JSON 函数在 DuckDB JSON 扩展中的对应函数
+const JSON_FUNCTIONS: &[(&str, &str)] = &[
+    ("JSONB_ARRAY_LENGTH", "json_array_length"),
+    ("JSON_BUILD_ARRAY", "json_array"),
+    ("JSONB_BUILD_ARRAY", "json_array"),
+    ("JSON_BUILD_OBJECT", "json_object"),
+    ("JSONB_BUILD_OBJECT", "json_object"),
+    ("TO_JSONB", "to_json"),
+    ("JSONB_EXTRACT_PATH", "json_extract_path"),
+    ("JSONB_EXTRACT_PATH_TEXT", "json_extract_path_text"),
+    ("JSON_AGG", "json_group_array"),
+    ("JSONB_AGG", "json_group_array"),
+    ("JSON_OBJECT_AGG", "json_group_object"),
+    ("JSONB_OBJECT_AGG", "json_group_object"),
+    ("JSONB_PRETTY", "json_pretty"),
+];
+
+// DuckDB 原生支持 -> 和 ->>, 这里只改写它不认识的部分:
+// 类型位置上的 jsonb 改为 json, jsonb_* 等函数换成 DuckDB 的函数, #> / #>> '{a,b}' 改为 JSON pointer 形式的 -> / ->> '/a/b'.
+// 基于 tokenize, 不会改动字符串和引号标识符里的内容
+fn rewrite_json(sql: &str) -> (String, Vec<Edit>) {
+    let tokens = tokenize(sql);
+    let mut result = String::with_capacity(sql.len());
+    let mut edits = vec![];
+    let mut copied = 0;
+    // 每个未闭合的左括号前面的单词, 用于识别 CAST(... AS jsonb)
+    let mut parens: Vec<&str> = Vec::new();
+    let mut i = 0;
+    while i < tokens.len() {
+        let token = &tokens[i];
+        match token.kind {
+            TokenKind::Symbol('(') => parens.push(i.checked_sub(1).and_then(|prev| word_at(&tokens, prev)).unwrap_or("")),
+            TokenKind::Symbol(')') => {
+                parens.pop();
+            }
+            _ => {}
+        }
+        let next_is_paren = tokens.get(i + 1).map(|next| &next.kind) == Some(&TokenKind::Symbol('('));
+        let (replacement, consumed) = match &token.kind {
+            TokenKind::Word(word) if word == "JSONB" && is_type_position(&tokens, i, &parens) => (Some("json".to_owned()), 1),
+            TokenKind::Word(word) if next_is_paren => (
+                JSON_FUNCTIONS
+                    .iter()
+                    .find(|(name, _)| *name == word.as_str())
+                    .map(|(_, function)| function.to_string()),
+                1,
+            ),
+            TokenKind::Symbol('#') => path_operator(sql, &tokens[i..]).map_or((None, 1), |(op, n)| (Some(op), n)),
+            _ => (None, 1),
+        };
+        if let Some(replacement) = replacement {
+            let end = tokens[i + consumed - 1].end;
+            result.push_str(&sql[copied..token.start]);
+            let start = result.len();
+            result.push_str(&replacement);
+            edits.push(Edit { output: start..result.len(), input: token.start..end });
+            copied = end;
+        }
+        i += consumed;
+    }
+    result.push_str(&sql[copied..]);
+    (result, edits)
+}
+
+fn word_at(tokens: &[Token], idx: usize) -> Option<&str> {
+    match &tokens.get(idx)?.kind {
+        TokenKind::Word(word) => Some(word.as_str()),
+        _ => None,
+    }
+}
+
+// 第 i 个 token 是否处在类型的位置: ::jsonb, CAST(x AS jsonb), ALTER COLUMN c TYPE jsonb,
+// 以及 CREATE TABLE 和 ADD COLUMN 的列定义. 其它位置上的 jsonb 是标识符, 保持不变
+fn is_type_position(tokens: &[Token], i: usize, parens: &[&str]) -> bool {
+    let Some(prev) = i.checked_sub(1) else {
+        return false;
+    };
+    match &tokens[prev].kind {
+        TokenKind::Symbol(':') => {
+            prev > 0 && tokens[prev - 1].kind == TokenKind::Symbol(':') && tokens[prev - 1].end == tokens[prev].start
+        }
+        TokenKind::Word(word) if word == "AS" && matches!(parens.last(), Some(&"CAST" | &"TRY_CAST")) => true,
+        TokenKind::Word(word) if word == "TYPE" => true,
+        // 前一个 token 是列名
+        TokenKind::Word(_) | TokenKind::QuotedIdentifier => match prev.checked_sub(1).map(|idx| &tokens[idx].kind) {
+            Some(TokenKind::Symbol('(' | ',')) => parens.len() == 1 && is_create_table(tokens),
+            Some(TokenKind::Word(word)) => {
+                word == "ADD" || (word == "COLUMN" && prev.checked_sub(2).and_then(|idx| word_at(tokens, idx)) == Some("ADD"))
+            }
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+// CREATE [TEMP] TABLE name (...), 第一个括号是列定义
+fn is_create_table(tokens: &[Token]) -> bool {
+    let mut words = tokens
+        .iter()
+        .take_while(|token| token.kind != TokenKind::Symbol('('))
+        .map(|token| match &token.kind {
+            TokenKind::Word(word) => word.as_str(),
+            _ => "",
+        });
+    words.next() == Some("CREATE") && words.any(|word| word == "TABLE")
+}
+
+// #> 或 #>> 后面跟着文本数组字面量的路径, 返回改写后的运算符和消耗的 token 数
+fn path_operator(sql: &str, tokens: &[Token]) -> Option<(String, usize)> {
+    let adjacent = |idx: usize| {
+        tokens.get(idx).filter(|token| token.kind == TokenKind::Symbol('>') && token.start == tokens[idx - 1].end)
+    };
+    adjacent(1)?;
+    let (operator, path_idx) = match adjacent(2) {
+        Some(_) => ("->>", 3),
+        None => ("->", 2),
+    };
+    let path = tokens.get(path_idx).filter(|token| token.kind == TokenKind::String)?;
+    let literal = sql[path.start..path.end].strip_prefix('\'')?.strip_suffix('\'')?.replace("''", "'");
+    let elements = literal.trim().strip_prefix('{')?.strip_suffix('}')?;
+    let pointer: String = elements
+        .split(',')
+        .filter(|element| !element.trim().is_empty())
+        .map(|element| {
+            let element = element.trim();
+            let element = element.strip_prefix('"').and_then(|e| e.strip_suffix('"')).unwrap_or(element);
+            format!("/{}", element.replace('~', "~0").replace('/', "~1"))
+        })
+        .collect();
+    let pointer = if pointer.is_empty() { "$".to_owned() } else { pointer.replace('\'', "''") };
+    Some((format!("{} '{}'", operator, pointer), path_idx + 1))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    // 关键字或未加引号的标识符, 已转为大写
+    Word(String),
+    QuotedIdentifier,
+    String,
+    Number,
+    Parameter,
+    Symbol(char),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    // 在原始 SQL 中的字节范围
+    pub start: usize,
+    pub end: usize,
+}
+
+// 简单的 SQL 词法分析: 跳过空白和注释, 识别字符串/引号标识符/dollar quote, 只用于语句分类和拆分
+pub fn tokenize(sql: &str) -> Vec<Token> {
+    let bytes = sql.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        let start = i;
+        let kind = match c {
+            b if b.is_ascii_whitespace() => {
+                i += 1;
+                continue;
+            }
+            b'-' if bytes.get(i + 1) == Some(&b'-') => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+                continue;
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                // 块注释允许嵌套
+                let mut depth = 0;
+                while i < bytes.len() {
+                    if bytes[i] == b'/' && bytes.get(i + 1) == Some(&b'*') {
+                        depth += 1;
+                        i += 2;
+                    } else if bytes[i] == b'*' && bytes.get(i + 1) == Some(&b'/') {
+                        depth -= 1;
+                        i += 2;
+                        if depth == 0 {
+                            break;
+                        }
+                    } else {
+                        i += 1;
+                    }
+                }
+                continue;
+            }
+            b'\'' => {
+                i = skip_quoted(bytes, i, b'\'');
+                TokenKind::String
+            }
+            b'e' | b'E' if bytes.get(i + 1) == Some(&b'\'') => {
+                i = skip_escaped_string(bytes, i + 1);
+                TokenKind::String
+            }
+            b'"' => {
+                i = skip_quoted(bytes, i, b'"');
+                TokenKind::QuotedIdentifier
+            }
+            b'$' => match dollar_quote_tag(sql, i) {
+                Some(tag) => {
+                    i = match sql[i + tag.len()..].find(tag) {
+                        Some(pos) => i + tag.len() + pos + tag.len(),
+                        None => bytes.len(),
+                    };
+                    TokenKind::String
+                }
+                None => {
+                    i += 1;
+                    while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                        i += 1;
+                    }
+                    TokenKind::Parameter
+                }
+            },
+            b'?' => {
+                i += 1;
+                TokenKind::Parameter
+            }
+            b if b.is_ascii_digit() => {
+                while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'.' || bytes[i] == b'_') {
+                    i += 1;
+                }
+                TokenKind::Number
+            }
+            b if b.is_ascii_alphabetic() || b == b'_' || b >= 0x80 => {
+                while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_' || bytes[i] >= 0x80) {
+                    i += 1;
+                }
+                TokenKind::Word(sql[start..i].to_ascii_uppercase())
+            }
+            _ => {
+                i += 1;
+                TokenKind::Symbol(c as char)
+            }
+        };
+        tokens.push(Token { kind, start, end: i });
+    }
+    tokens
+}
+
+// 引号内用两个引号转义, 返回结束引号之后的位置
+fn skip_quoted(bytes: &[u8], start: usize, quote: u8) -> usize {
+    let mut i = start + 1;
+    while i < bytes.len() {
+        if bytes[i] == quote {
+            if bytes.get(i + 1) == Some(&quote) {
+                i += 2;
+                continue;
+            }
+            return i + 1;
+        }
+        i += 1;
+    }
+    bytes.len()
+}
+
+// E'...' 字符串还允许反斜杠转义
+fn skip_escaped_string(bytes: &[u8], start: usize) -> usize {
+    let mut i = start + 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'\'' if bytes.get(i + 1) == Some(&b'\'') => i += 2,
+            b'\'' => return i + 1,
+            _ => i += 1,
+        }
+    }
+    bytes.len()
+}
+
+// $tag$ 或 $$ 开头的 dollar quote, 返回完整的 tag
+fn dollar_quote_tag(sql: &str, start: usize) -> Option<&str> {
+    let rest = &sql[start + 1..];
+    let end = rest.find('$')?;
+    let tag = &rest[..end];
+    if tag.chars().all(|c| c.is_ascii_alphabetic() || c == '_') {
+        Some(&sql[start..start + end + 2])
+    } else {
+        None
+    }
+}
+
+// 按分号拆分一次 Query 里的多条语句, 返回每条语句和它在原文中的字节偏移, 只有空白和注释的语句会被丢弃
+pub fn split_statements(sql: &str) -> Vec<(usize, &str)> {
+    let mut statements = Vec::new();
+    let mut start = 0;
+    for token in tokenize(sql) {
+        if token.kind == TokenKind::Symbol(';') {
+            push_statement(&mut statements, sql, start..token.start);
+            start = token.end;
+        }
+    }
+    push_statement(&mut statements, sql, start..sql.len());
+    statements
+}
+
+fn push_statement<'a>(statements: &mut Vec<(usize, &'a str)>, sql: &'a str, range: Range<usize>) {
+    let statement = &sql[range.clone()];
+    if !tokenize(statement).is_empty() {
+        let offset = range.start + statement.len() - statement.trim_start().len();
+        statements.push((offset, statement.trim()));
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatementKind {
+    // 语句的主关键字, 如 SELECT / INSERT / CREATE, WITH 和 EXPLAIN 之类的前缀已经跳过
+    pub keyword: String,
+    // PostgreSQL 的 command tag, 不含行数
+    pub tag: String,
+    // command tag 是否需要带上行数, 如 INSERT 0 3 / UPDATE 2 / SELECT 42
+    pub with_rows: bool,
+    pub returns_rows: bool,
+}
+
+impl StatementKind {
+    pub fn is_transaction_control(&self) -> bool {
+        matches!(self.keyword.as_str(), "BEGIN" | "START" | "COMMIT" | "END" | "ROLLBACK" | "ABORT")
+    }
+}
+
+// 产生结果集的语句
+const QUERY_KEYWORDS: &[&str] = &[
+    "SELECT", "VALUES", "TABLE", "FROM", "SHOW", "DESCRIBE", "SUMMARIZE", "EXPLAIN",
+    "PIVOT", "PIVOT_WIDER", "UNPIVOT", "PIVOT_LONGER", "PRAGMA", "CALL",
+];
+
+// WITH 之后可能出现的主语句
+const WITH_BODY_KEYWORDS: &[&str] = &[
+    "SELECT", "VALUES", "TABLE", "FROM", "INSERT", "UPDATE", "DELETE", "PIVOT", "UNPIVOT",
+];
+
+pub fn classify_statement(sql: &str) -> StatementKind {
+    classify_tokens(&tokenize(sql))
+}
+
+pub fn classify_tokens(tokens: &[Token]) -> StatementKind {
+    let words: Vec<Option<&str>> = tokens
+        .iter()
+        .map(|token| match &token.kind {
+            TokenKind::Word(word) => Some(word.as_str()),
+            _ => None,
+        })
+        .collect();
+    // 跳过开头的括号, 如 (SELECT 1) UNION (SELECT 2)
+    let mut pos = 0;
+    while pos < tokens.len() && tokens[pos].kind == TokenKind::Symbol('(') {
+        pos += 1;
+    }
+    let keyword = match words.get(pos).copied().flatten() {
+        Some("WITH") => main_keyword_after_with(tokens, &words, pos + 1),
+        Some(keyword) => keyword,
+        None => "",
+    };
+    // 主关键字之后的单词, 用于确定 CREATE / DROP / ALTER 的对象类型
+    let object_words: Vec<&str> = words.iter().skip(pos + 1).flatten().copied().collect();
+
+    let returns_rows = QUERY_KEYWORDS.contains(&keyword)
+        || (matches!(keyword, "INSERT" | "UPDATE" | "DELETE") && has_top_level_word(tokens, &words, "RETURNING"));
+    let (tag, with_rows) = match keyword {
+        "SELECT" | "VALUES" | "TABLE" | "FROM" | "PIVOT" | "PIVOT_WIDER" | "UNPIVOT" | "PIVOT_LONGER"
+        | "SUMMARIZE" | "DESCRIBE" | "PRAGMA" | "CALL" => ("SELECT".to_owned(), true),
+        "INSERT" | "UPDATE" | "DELETE" | "MERGE" | "COPY" => (keyword.to_owned(), true),
+        "CREATE" => {
+            let object = object_words
+                .iter()
+                .find(|word| !CREATE_MODIFIERS.contains(word))
+                .copied()
+                .unwrap_or("");
+            if object == "TABLE" && has_top_level_word(tokens, &words, "AS") {
+                // CREATE TABLE ... AS 在 PostgreSQL 中报告为 SELECT n
+                ("SELECT".to_owned(), true)
+            } else {
+                (format!("CREATE {}", object).trim_end().to_owned(), false)
+            }
+        }
+        "DROP" | "ALTER" => {
+            let object = object_words.first().copied().unwrap_or("");
+            (format!("{} {}", keyword, object).trim_end().to_owned(), false)
+        }
+        "START" => ("START TRANSACTION".to_owned(), false),
+        "END" => ("COMMIT".to_owned(), false),
+        "ABORT" => ("ROLLBACK".to_owned(), false),
+        "TRUNCATE" => ("TRUNCATE TABLE".to_owned(), false),
+        "FORCE" => ("CHECKPOINT".to_owned(), false),
+        keyword => (keyword.to_owned(), false),
+    };
+    StatementKind {
+        keyword: keyword.to_owned(),
+        tag,
+        with_rows,
+        returns_rows,
+    }
+}
+
+// CREATE 和对象类型之间可能出现的修饰词
+const CREATE_MODIFIERS: &[&str] = &[
+    "OR", "REPLACE", "TEMP", "TEMPORARY", "PERSISTENT", "UNIQUE", "UNLOGGED", "GLOBAL", "LOCAL",
+];
+
+fn main_keyword_after_with<'a>(tokens: &[Token], words: &[Option<&'a str>], start: usize) -> &'a str {
+    let mut depth = 0;
+    for (token, word) in tokens.iter().zip(words.iter()).skip(start) {
+        match token.kind {
+            TokenKind::Symbol('(') => depth += 1,
+            TokenKind::Symbol(')') => depth -= 1,
+            _ => {
+                if let Some(word) = word {
+                    if depth == 0 && WITH_BODY_KEYWORDS.contains(word) {
+                        return word;
+                    }
+                }
+            }
+        }
+    }
+    "SELECT"
+}
+
+fn has_top_level_word(tokens: &[Token], words: &[Option<&str>], expected: &str) -> bool {
+    let mut depth = 0;
+    for (token, word) in tokens.iter().zip(words.iter()) {
+        match token.kind {
+            TokenKind::Symbol('(') => depth += 1,
+            TokenKind::Symbol(')') => depth -= 1,
+            _ => {
+                if depth == 0 && *word == Some(expected) {
+                    return true;
+                }
+            }
+        }
+    }
+    false
+}
+
+// SET / RESET 语句修改的变量, name 已转为小写, RESET 时 value 为 None
+#[derive(Debug, Clone, PartialEq)]
+pub struct SetVariable {
+    pub name: String,
+    pub value: Option<String>,
+}
+
+// 解析 `SET [SESSION | LOCAL] name { = | TO } value` 和 `RESET name`,
+// 用于在服务端处理 DuckDB 没有的 PostgreSQL 会话变量
+pub fn parse_set_variable(sql: &str) -> Option<SetVariable> {
+    let tokens = tokenize(sql);
+    let text = |token: &Token| &sql[token.start..token.end];
+    let mut iter = tokens.iter().filter(|token| token.kind != TokenKind::Symbol(';')).peekable();
+    let reset = match &iter.next()?.kind {
+        TokenKind::Word(word) if word == "SET" => false,
+        TokenKind::Word(word) if word == "RESET" => true,
+        _ => return None,
+    };
+    if let Some(TokenKind::Word(word)) = iter.peek().map(|token| &token.kind) {
+        if word == "SESSION" || word == "LOCAL" {
+            iter.next();
+        }
+    }
+    let name_token = iter.next()?;
+    let name = match &name_token.kind {
+        TokenKind::Word(word) => word.to_lowercase(),
+        TokenKind::QuotedIdentifier => text(name_token).trim_matches('"').to_lowercase(),
+        _ => return None,
+    };
+    if reset {
+        return iter.next().is_none().then_some(SetVariable { name, value: None });
+    }
+    match &iter.next()?.kind {
+        TokenKind::Symbol('=') => {}
+        TokenKind::Word(word) if word == "TO" => {}
+        _ => return None,
+    }
+    let value_token = iter.next()?;
+    if iter.next().is_some() {
+        return None;
+    }
+    let value = match &value_token.kind {
+        TokenKind::String => text(value_token).trim_matches('\'').replace("''", "'"),
+        TokenKind::Word(word) if word == "DEFAULT" => return Some(SetVariable { name, value: None }),
+        TokenKind::Word(_) | TokenKind::QuotedIdentifier | TokenKind::Number => {
+            text(value_token).trim_matches('"').to_owned()
+        }
+        _ => return None,
+    };
+    Some(SetVariable { name, value: Some(value) })
+}
+
+// 不能作为子查询的语句换成结果列相同的 SELECT, 用于不执行语句就取得结果列:
+// INSERT / UPDATE / DELETE ... RETURNING 换成从目标表查询 RETURNING 的列, PRAGMA 和 CALL 换成对应的表函数.
+// 其它语句返回 None, 本身就可以作为子查询
+pub fn describe_query(sql: &str) -> Option<String> {
+    let sql = sql.trim_end().trim_end_matches(';');
+    let tokens = tokenize(sql);
+    let words: Vec<Option<&str>> = tokens
+        .iter()
+        .map(|token| match &token.kind {
+            TokenKind::Word(word) => Some(word.as_str()),
+            _ => None,
+        })
+        .collect();
+    let rest = |idx: usize| tokens.get(idx).map_or("", |token| &sql[token.start..]);
+    match words.first().copied().flatten()? {
+        "CALL" => return Some(format!("SELECT * FROM {}", rest(1))),
+        "PRAGMA" => {
+            let name = words.get(1).copied().flatten()?;
+            return match tokens.get(2).map(|token| &token.kind) {
+                Some(TokenKind::Symbol('(')) => Some(format!("SELECT * FROM pragma_{}{}", name.to_lowercase(), rest(2))),
+                None => Some(format!("SELECT * FROM pragma_{}()", name.to_lowercase())),
+                _ => None,
+            };
+        }
+        _ => {}
+    }
+
+    // WITH 子句保留在 SELECT 前面
+    let mut depth = 0;
+    let mut keyword = None;
+    let mut returning = None;
+    for (idx, (token, word)) in tokens.iter().zip(words.iter()).enumerate() {
+        match token.kind {
+            TokenKind::Symbol('(') => depth += 1,
+            TokenKind::Symbol(')') => depth -= 1,
+            _ if depth == 0 => match word {
+                Some("INSERT" | "UPDATE" | "DELETE") if keyword.is_none() => keyword = Some(idx),
+                Some("RETURNING") if keyword.is_some() => returning = Some(idx),
+                _ => {}
+            },
+            _ => {}
+        }
+    }
+    let (keyword, returning) = (keyword?, returning?);
+    // 目标表在 INSERT INTO / DELETE FROM 之后, UPDATE 直接跟表名
+    let mut idx = keyword + 1;
+    if words[keyword] != Some("UPDATE") {
+        idx += words[idx..returning].iter().position(|word| matches!(word, Some("INTO" | "FROM")))? + 1;
+    }
+    let start = idx;
+    while idx + 2 < returning
+        && tokens[idx + 1].kind == TokenKind::Symbol('.')
+        && matches!(tokens[idx + 2].kind, TokenKind::Word(_) | TokenKind::QuotedIdentifier)
+    {
+        idx += 2;
+    }
+    let mut target = sql[tokens[start].start..tokens[idx].end].to_owned();
+    // 表的别名
+    let alias = match words.get(idx + 1).copied().flatten() {
+        Some("AS") if idx + 2 < returning => Some(&tokens[idx + 2]),
+        Some(word) if words[keyword] != Some("INSERT") && !TARGET_FOLLOWERS.contains(&word) => Some(&tokens[idx + 1]),
+        _ => None,
+    };
+    if let Some(alias) = alias {
+        target = format!("{} AS {}", target, &sql[alias.start..alias.end]);
+    }
+    Some(format!("{}SELECT {} FROM {}", &sql[..tokens[keyword].start], rest(returning + 1), target))
+}
+
+// UPDATE / DELETE 的目标表之后不是别名的关键字
+const TARGET_FOLLOWERS: &[&str] = &["SET", "WHERE", "USING", "FROM", "RETURNING"];
+
+// 参数个数, $n 取最大的编号, ? 按出现次数计算
+pub fn parameter_count(sql: &str) -> usize {
+    let mut positional = 0;
+    let mut numbered = 0;
+    for token in tokenize(sql) {
+        if token.kind != TokenKind::Parameter {
+            continue;
+        }
+        match sql[token.start..token.end].strip_prefix('$') {
+            Some(number) => numbered = numbered.max(number.parse::<usize>().unwrap_or(0)),
+            None => positional += 1,
+        }
+    }
+    positional.max(numbered)
+}
+
+// Parse 时确定参数类型: client 显式指定的类型优先, 其余按 UNKNOWN 以文本绑定, 执行时由 DuckDB 转换或报告错误.
+// duckdb-rs 没有提供预编译语句的参数类型, 这里也不在 DuckDB 中执行任何东西, 不会影响会话的事务和正在执行的语句
+pub fn parameter_types(sql: &str, types: &[Type]) -> Vec<Type> {
+    (0..parameter_count(sql).max(types.len()))
+        .map(|idx| types.get(idx).cloned().unwrap_or(Type::UNKNOWN))
+        .collect()
+}
+
+// 改写后的 SQL 和 Parse 时确定的参数类型, client 没有指定的类型为 UNKNOWN.
+// 保留原文和对应关系, 出错位置按原文报告
+#[derive(Debug, Clone)]
+pub struct FatherDuckStatement {
+    pub query: String,
+    pub source: String,
+    pub source_map: SourceMap,
+    pub parameter_types: Vec<Type>,
+}
+
+impl FatherDuckStatement {
+    pub fn map_error(&self, error: PgWireError) -> PgWireError {
+        map_error_position(error, &self.query, &self.source, &self.source_map)
     }
-    result
 }
 
 #[derive(new, Debug, Default)]
@@ -38,11 +719,12 @@ pub struct FatherDuckQueryParser;
 
 #[async_trait]
 impl QueryParser for FatherDuckQueryParser {
-    type Statement = String;
+    type Statement = FatherDuckStatement;
 
-    async fn parse_sql(&self, sql: &str, _types: &[Type]) -> PgWireResult<Self::Statement> {
-        let new_sql = rewrite_query(sql);
-        Ok(new_sql.to_owned())
+    async fn parse_sql(&self, sql: &str, types: &[Type]) -> PgWireResult<Self::Statement> {
+        let (query, source_map) = rewrite_query(sql);
+        let parameter_types = parameter_types(&query, types);
+        Ok(FatherDuckStatement { query, source: sql.to_owned(), source_map, parameter_types })
     }
 }
 
@@ -51,17 +733,195 @@ impl QueryParser for FatherDuckQueryParser {
 mod tests {
     use super::*;
 
+    fn rewrite(sql: &str) -> String {
+        rewrite_query(sql).0
+    }
+
     #[test]
     fn test_rewrite_regclass() {
         let sql = "'pg_namespace'::regclass";
-        let new_sql = rewrite_query(sql);
+        let new_sql = rewrite(sql);
         assert_eq!(new_sql, "(SELECT oid FROM pg_class WHERE relname = 'pg_namespace')");
     }
     
+    #[test]
+    fn test_rewrite_pg_catalog() {
+        let new_sql = rewrite("SELECT * FROM pg_catalog.pg_enum JOIN PG_CATALOG.pg_type ON true");
+        assert_eq!(new_sql, "SELECT * FROM pg_enum JOIN pg_type ON true");
+    }
+
     #[test]
     fn test_rewrite_show() {
         let sql = "SHOW search_path";
-        let new_sql = rewrite_query(sql);
+        let new_sql = rewrite(sql);
         assert_eq!(new_sql, "SELECT current_setting('search_path') AS search_path");
     }
+
+    #[test]
+    fn test_rewrite_json() {
+        assert_eq!(
+            rewrite("SELECT j #>> '{a,0}', j#>'{\"b/c\"}', j ->> 'x' FROM t"),
+            "SELECT j ->> '/a/0', j-> '/b~1c', j ->> 'x' FROM t"
+        );
+        assert_eq!(
+            rewrite("SELECT '{\"a\":1}'::jsonb, jsonb_build_object('jsonb', 1), CAST(x AS JSONB)"),
+            "SELECT '{\"a\":1}'::json, json_object('jsonb', 1), CAST(x AS json)"
+        );
+        assert_eq!(rewrite("SELECT j #> p FROM t"), "SELECT j #> p FROM t");
+    }
+
+    #[test]
+    fn test_rewrite_jsonb_type() {
+        // 标识符 jsonb 不改写
+        assert_eq!(rewrite("SELECT jsonb, x AS jsonb FROM jsonb"), "SELECT jsonb, x AS jsonb FROM jsonb");
+        assert_eq!(rewrite("SELECT TRY_CAST(f(x) AS jsonb)"), "SELECT TRY_CAST(f(x) AS json)");
+        assert_eq!(
+            rewrite("CREATE TABLE t (jsonb jsonb, \"j\" JSONB[], i INTEGER)"),
+            "CREATE TABLE t (jsonb json, \"j\" json[], i INTEGER)"
+        );
+        assert_eq!(rewrite("ALTER TABLE t ADD COLUMN c jsonb"), "ALTER TABLE t ADD COLUMN c json");
+        assert_eq!(rewrite("ALTER TABLE t ALTER c SET DATA TYPE jsonb"), "ALTER TABLE t ALTER c SET DATA TYPE json");
+        assert_eq!(rewrite("ALTER TABLE t RENAME COLUMN jsonb TO j"), "ALTER TABLE t RENAME COLUMN jsonb TO j");
+        assert_eq!(rewrite("INSERT INTO t (jsonb) VALUES (1)"), "INSERT INTO t (jsonb) VALUES (1)");
+    }
+
+    #[test]
+    fn test_parameter_count() {
+        assert_eq!(parameter_count("SELECT 1"), 0);
+        assert_eq!(parameter_count("SELECT $2, $1, '$3'"), 2);
+        assert_eq!(parameter_count("SELECT ?, ? -- ?"), 2);
+    }
+
+    #[test]
+    fn test_tokenize_skip_comments_and_strings() {
+        let tokens = tokenize("/* a /* nested */ comment */ SELECT 'it''s; --' -- tail\n, $$x;y$$, \"a\"\"b\"");
+        let kinds: Vec<TokenKind> = tokens.into_iter().map(|token| token.kind).collect();
+        assert_eq!(kinds, vec![
+            TokenKind::Word("SELECT".to_owned()),
+            TokenKind::String,
+            TokenKind::Symbol(','),
+            TokenKind::String,
+            TokenKind::Symbol(','),
+            TokenKind::QuotedIdentifier,
+        ]);
+    }
+
+    #[test]
+    fn test_split_statements() {
+        let sql = "CREATE TABLE a(i int); INSERT INTO a VALUES (1);\n-- only comment;\nSELECT ';' FROM a;";
+        assert_eq!(split_statements(sql), vec![
+            (0, "CREATE TABLE a(i int)"),
+            (23, "INSERT INTO a VALUES (1)"),
+            (49, "-- only comment;\nSELECT ';' FROM a"),
+        ]);
+    }
+
+    #[test]
+    fn test_classify_statement() {
+        let kind = classify_statement("-- comment\nWITH t AS (SELECT 1) SELECT * FROM t");
+        assert_eq!((kind.keyword.as_str(), kind.returns_rows), ("SELECT", true));
+        let kind = classify_statement("WITH t AS (SELECT 1 AS i) INSERT INTO a SELECT i FROM t");
+        assert_eq!((kind.keyword.as_str(), kind.returns_rows), ("INSERT", false));
+        let kind = classify_statement("INSERT INTO a VALUES (1) RETURNING i");
+        assert_eq!((kind.keyword.as_str(), kind.returns_rows), ("INSERT", true));
+        let kind = classify_statement("FROM tbl");
+        assert_eq!((kind.keyword.as_str(), kind.returns_rows), ("FROM", true));
+        let kind = classify_statement("EXPLAIN SELECT 1");
+        assert_eq!((kind.keyword.as_str(), kind.returns_rows), ("EXPLAIN", true));
+        let kind = classify_statement("CREATE VIEW v AS SELECT 1");
+        assert_eq!((kind.keyword.as_str(), kind.returns_rows), ("CREATE", false));
+    }
+
+    #[test]
+    fn test_parse_set_variable() {
+        let variable = |name: &str, value: Option<&str>| Some(SetVariable {
+            name: name.to_owned(),
+            value: value.map(str::to_owned),
+        });
+        assert_eq!(parse_set_variable("SET IntervalStyle = 'iso_8601'"), variable("intervalstyle", Some("iso_8601")));
+        assert_eq!(parse_set_variable("set session intervalstyle to postgres;"), variable("intervalstyle", Some("postgres")));
+        assert_eq!(parse_set_variable("SET \"IntervalStyle\" TO DEFAULT"), variable("intervalstyle", None));
+        assert_eq!(parse_set_variable("RESET IntervalStyle"), variable("intervalstyle", None));
+        assert_eq!(parse_set_variable("SET threads = 4"), variable("threads", Some("4")));
+        assert_eq!(parse_set_variable("SET VARIABLE x = 1 + 1"), None);
+        assert_eq!(parse_set_variable("SELECT 1"), None);
+    }
+
+    #[test]
+    fn test_command_tag() {
+        let tags: Vec<(String, bool)> = [
+            "CREATE OR REPLACE TEMP TABLE t (i INTEGER)",
+            "CREATE TABLE t AS SELECT 1",
+            "CREATE UNIQUE INDEX idx ON t (i)",
+            "DROP VIEW IF EXISTS v",
+            "ALTER TABLE t ADD COLUMN j INTEGER",
+            "BEGIN TRANSACTION",
+            "ABORT",
+            "SET threads = 4",
+            "DELETE FROM t",
+            "VALUES (1)",
+        ]
+        .iter()
+        .map(|sql| {
+            let kind = classify_statement(sql);
+            (kind.tag, kind.with_rows)
+        })
+        .collect();
+        assert_eq!(tags, vec![
+            ("CREATE TABLE".to_owned(), false),
+            ("SELECT".to_owned(), true),
+            ("CREATE INDEX".to_owned(), false),
+            ("DROP VIEW".to_owned(), false),
+            ("ALTER TABLE".to_owned(), false),
+            ("BEGIN".to_owned(), false),
+            ("ROLLBACK".to_owned(), false),
+            ("SET".to_owned(), false),
+            ("DELETE".to_owned(), true),
+            ("SELECT".to_owned(), true),
+        ]);
+    }
+
+    #[test]
+    fn test_describe_query() {
+        let describe = |sql: &str| describe_query(sql);
+        assert_eq!(describe("SELECT 1"), None);
+        assert_eq!(describe("INSERT INTO t VALUES (1)"), None);
+        assert_eq!(describe("INSERT INTO s.t (i) VALUES ($1) RETURNING i, s;"), Some("SELECT i, s FROM s.t".to_owned()));
+        assert_eq!(describe("UPDATE t x SET i = 2 RETURNING x.i"), Some("SELECT x.i FROM t AS x".to_owned()));
+        assert_eq!(describe("DELETE FROM \"T\" AS x WHERE i = 1 RETURNING *"), Some("SELECT * FROM \"T\" AS x".to_owned()));
+        assert_eq!(
+            describe("WITH v AS (SELECT 1 AS i) DELETE FROM t USING v WHERE t.i = v.i RETURNING t.i"),
+            Some("WITH v AS (SELECT 1 AS i) SELECT t.i FROM t".to_owned())
+        );
+        assert_eq!(describe("PRAGMA table_info('t')"), Some("SELECT * FROM pragma_table_info('t')".to_owned()));
+        assert_eq!(describe("PRAGMA version"), Some("SELECT * FROM pragma_version()".to_owned()));
+        assert_eq!(describe("PRAGMA threads = 4"), None);
+        assert_eq!(describe("CALL pragma_version()"), Some("SELECT * FROM pragma_version()".to_owned()));
+    }
+
+    // 出错位置从改写后的 SQL 换算回原文: 改写前后的文本, 被替换的区间, 以及 simple query 里语句的偏移
+    #[test]
+    fn test_source_map() {
+        let position = |original: &str, statement: &str, rewritten_at: &str| {
+            let (offset, statement) = split_statements(original)
+                .into_iter()
+                .find(|(_, text)| *text == statement)
+                .unwrap();
+            let (rewritten, source_map) = rewrite_query(statement);
+            let position = rewritten[..rewritten.find(rewritten_at).unwrap()].chars().count() + 1;
+            source_map.with_offset(offset).original_position(&rewritten, original, position)
+        };
+        let original = "SELECT 1;\n  SELECT 'é'::jsonb, jsonb_build_object('a', x) FROM pg_catalog.pg_type WHERE y";
+        let statement = "SELECT 'é'::jsonb, jsonb_build_object('a', x) FROM pg_catalog.pg_type WHERE y";
+        let expected = |text: &str| Some(original[..original.find(text).unwrap()].chars().count() + 1);
+        assert_eq!(position(original, statement, "x)"), expected("x)"));
+        assert_eq!(position(original, statement, "WHERE y"), expected("WHERE y"));
+        // 落在替换出来的文本里时指向被替换文本的开头
+        assert_eq!(position(original, statement, "_object"), expected("jsonb_build_object"));
+        assert_eq!(position(original, statement, "pg_type"), expected("pg_catalog.pg_type"));
+
+        let (rewritten, source_map) = rewrite_query("  CALL f()");
+        assert_eq!(rewritten, "SELECT * FROM f()");
+        assert_eq!(source_map.original_position(&rewritten, "  CALL f()", rewritten.find("()").unwrap() + 1), Some(9));
+    }
 }
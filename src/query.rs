@@ -1,48 +1,664 @@
 
-use std::sync::Arc;
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Debug;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
 use std::vec;
 
 use async_trait::async_trait;
-use duckdb::arrow::array::PrimitiveArray;
-use duckdb::arrow::datatypes::{DataType, TimeUnit};
-use duckdb::{params, Rows};
-use duckdb::{types::ValueRef, Statement, ToSql};
+use duckdb::arrow::datatypes::{DataType, FieldRef, Schema, SchemaRef};
+use duckdb::arrow::record_batch::RecordBatch;
+use duckdb::types::{Null, Value};
+use duckdb::{Connection, Statement, ToSql};
 
 use futures::stream;
-use futures::Stream;
+use futures::{Sink, SinkExt, Stream};
+use tokio::sync::{mpsc, oneshot};
+use pgwire::api::copy::{send_copy_in_response, CopyHandler};
 use pgwire::api::portal::{Format, Portal};
 use pgwire::api::query::{ExtendedQueryHandler, SimpleQueryHandler};
 use pgwire::api::results::{
-    DataRowEncoder, DescribePortalResponse, DescribeStatementResponse, FieldFormat, FieldInfo, QueryResponse, Response, Tag
+    CopyResponse, DataRowEncoder, DescribePortalResponse, DescribeStatementResponse, FieldFormat, FieldInfo, QueryResponse,
+    Response, Tag,
 };
 use pgwire::api::stmt::StoredStatement;
-use pgwire::api::{ClientInfo, Type};
+use pgwire::api::store::PortalStore;
+use pgwire::api::{ClientInfo, ClientPortalStore, PgWireConnectionState, Type, DEFAULT_NAME};
 use pgwire::error::{ErrorInfo, PgWireError, PgWireResult};
-use pgwire::messages::data::DataRow;
-use chrono::{NaiveDate, NaiveTime, DateTime, Duration};
+use pgwire::messages::copy::{CopyData, CopyDone, CopyFail};
+use pgwire::messages::data::{DataRow, RowDescription};
+use pgwire::messages::extendedquery::{
+    Close, CloseComplete, Execute, PortalSuspended, Sync as PgSync, TARGET_TYPE_BYTE_PORTAL, TARGET_TYPE_BYTE_STATEMENT,
+};
+use pgwire::messages::response::{EmptyQueryResponse, ReadyForQuery, TransactionStatus};
+use pgwire::messages::simplequery::Query;
+use pgwire::messages::startup::ParameterStatus;
+use pgwire::messages::PgWireBackendMessage;
+use postgres_types::{FromSql, Kind};
+use chrono::{NaiveDateTime, DateTime, FixedOffset, Utc};
+use chrono_tz::Tz;
 use lazy_static::lazy_static;
-use fancy_regex::Regex;
 
-use crate::parser::FatherDuckQueryParser;
-use crate::parser::rewrite_query;
+use crate::parser::{FatherDuckQueryParser, FatherDuckStatement};
+use crate::parser::{classify_statement, classify_tokens, describe_query, parse_set_variable, rewrite_query, split_statements, tokenize, StatementKind, TokenKind};
 
-use crate::error::UnknownError;
-use crate::connection::MyConnection;
+use crate::catalog::{bump_catalog_version, catalog_version, find_enum_by_type, refresh_pg_catalog, PgEnum};
+use crate::copy::{copy_aborted, parse_copy_from_stdin, spawn_copy_in, CopyIn, CopyMessage, CopyStatement};
+use crate::connection::{current_time_zone, CachedStatement, MyConnection, session_worker_gone};
+use crate::error::{into_pg_error, invalid_value_error, map_error_position, unsupported_error};
+use crate::types::{
+    array_param_literal, array_value, bit_param, bytea_param, into_pg_field_type, json_param, text_param, IntervalStyle,
+    PgInterval, PgValue, SessionSettings,
+};
 
 pub struct FatherDuckQueryHandler {
     conn: MyConnection,
     query_parser: Arc<FatherDuckQueryParser>,
+    // 执行过的 portal 的结果集. 指定了 max_rows 没有取完的, 下一次 Execute 同名 portal 时继续取;
+    // 已经取完的再次 Execute 时返回 0 行, 不会重新执行
+    portals: Mutex<HashMap<String, (Arc<Portal<FatherDuckStatement>>, PortalState)>>,
+    // 由 BEGIN / COMMIT / ROLLBACK 和执行错误推导出的事务状态, 通过 ReadyForQuery 报告给 client
+    transaction_status: Mutex<TransactionStatus>,
+    // 影响结果编码的会话变量, 以及最近一次通过 ParameterStatus 报告给 client 的值
+    session_settings: Mutex<SessionSettings>,
+    reported_settings: Mutex<SessionSettings>,
+    // 当前可见的 ENUM 类型, 以及生成它们和本会话 pg_type / pg_enum 时的 catalog 版本
+    pg_enums: Mutex<Arc<Vec<PgEnum>>>,
+    pg_catalog_version: Mutex<Option<u64>>,
+    // 正在进行的 COPY FROM STDIN
+    copy_in: Mutex<Option<CopyIn>>,
+    // 当前事务中执行过 DDL, 事务结束时需要再通知其它会话一次
+    catalog_changed: Mutex<bool>,
 }
 
 impl FatherDuckQueryHandler {
-    pub fn new(conn: MyConnection) -> FatherDuckQueryHandler {
-        FatherDuckQueryHandler {
+    pub fn new(conn: MyConnection, time_zone: Tz) -> Arc<FatherDuckQueryHandler> {
+        let settings = SessionSettings {
+            time_zone,
+            interval_style: IntervalStyle::Postgres,
+        };
+        Arc::new(FatherDuckQueryHandler {
             conn: conn,
             query_parser: Arc::new(FatherDuckQueryParser::new()),
+            portals: Mutex::new(HashMap::new()),
+            transaction_status: Mutex::new(TransactionStatus::Idle),
+            session_settings: Mutex::new(settings),
+            reported_settings: Mutex::new(settings),
+            pg_enums: Mutex::new(Arc::new(vec![])),
+            pg_catalog_version: Mutex::new(None),
+            copy_in: Mutex::new(None),
+            catalog_changed: Mutex::new(false),
+        })
+    }
+
+    // 在会话工作线程上执行查询, 表头就绪后立即返回, 数据行由工作线程边读边编码, 经有界 channel 流式发送
+    async fn query_rows(
+        &self,
+        query: String,
+        name: Option<String>,
+        params: Vec<Box<dyn ToSql + Send>>,
+        format: Format,
+        kind: &StatementKind,
+    ) -> PgWireResult<PortalCursor> {
+        let (header_sender, header_receiver) = oneshot::channel();
+        let (row_sender, row_receiver) = mpsc::channel(ROW_CHANNEL_CAPACITY);
+        let settings = self.session_settings();
+        let enums = self.pg_enums();
+        self.conn.spawn_cached(move |statements| {
+            let conn = statements.connection();
+            let CachedStatement { statement: stmt, fields: cached_fields } = match statements.prepare(name.as_deref(), &query) {
+                Ok(cached) => cached,
+                Err(e) => {
+                    let _ = header_sender.send(Err(into_pg_error(e, &query)));
+                    return;
+                }
+            };
+            let params_ref = params
+                .iter()
+                .map(|f| f.as_ref() as &dyn duckdb::ToSql)
+                .collect::<Vec<&dyn duckdb::ToSql>>();
+            let describe = |schema: &Schema| {
+                row_desc_from_schema(schema, &format)
+                    .and_then(|header| resolve_declared_types(conn, &query, Some(&params_ref), schema, header, &enums))
+            };
+            // duckdb-rs 只有执行过的语句才有 Arrow schema, 先通过 LIMIT 0 的子查询取得 schema, 再流式执行语句本身.
+            // 不能作为子查询的语句(INSERT ... RETURNING, PRAGMA, CALL 等)直接执行, 结果在 DuckDB 中物化后逐批读取
+            let result = match probe_schema(conn, &query, Some(&params_ref)) {
+                Ok(schema) => describe(&schema).and_then(|header| {
+                    let batches = stmt
+                        .stream_arrow::<&[&dyn duckdb::ToSql]>(params_ref.as_ref(), schema.clone())
+                        .map_err(|e| into_pg_error(e, &query))?;
+                    Ok((schema, header, Box::new(batches) as RecordBatches))
+                }),
+                Err(_) => stmt
+                    .query_arrow::<&[&dyn duckdb::ToSql]>(params_ref.as_ref())
+                    .map_err(|e| into_pg_error(e, &query))
+                    .and_then(|batches| {
+                        let schema = batches.get_schema();
+                        let header = describe(&schema)?;
+                        Ok((schema, header, Box::new(batches) as RecordBatches))
+                    }),
+            };
+            let (schema, header, batches) = match result {
+                Ok(result) => result,
+                Err(e) => {
+                    let _ = header_sender.send(Err(e));
+                    return;
+                }
+            };
+            *cached_fields = Some(header.clone());
+            if header_sender.send(Ok(header.clone())).is_err() {
+                return;
+            }
+            let column_fields: Vec<FieldRef> = schema.fields().iter().cloned().collect();
+            for batch in batches {
+                if !encode_batch(&batch, &header, &column_fields, settings, &row_sender) {
+                    break;
+                }
+            }
+        })?;
+
+        let header = header_receiver.await.map_err(|_| session_worker_gone())??;
+        Ok(PortalCursor {
+            header,
+            rows: row_receiver,
+            buffered: VecDeque::new(),
+            command_tag: query_command_tag(kind),
+            with_rows: kind.with_rows,
+        })
+    }
+
+    // 执行 portal: 查询返回仍在工作线程上持续产出数据的游标, 其余语句直接返回 Tag
+    async fn execute_portal(&self, portal: &Portal<FatherDuckStatement>) -> PgWireResult<PortalResult> {
+        let statement = &portal.statement.statement;
+        // 参数解码失败和执行失败一样使事务进入失败状态
+        let params = get_params(portal).inspect_err(|_| self.fail_transaction())?;
+        let format = portal.result_column_format.clone();
+        let result = self
+            .run_statement(statement.query.clone(), statement_name(&portal.statement), params, format)
+            .await
+            .map_err(|e| statement.map_error(e));
+        self.publish_catalog_change();
+        result
+    }
+
+    // 执行 simple query 里的一条语句, statement 从 source 的 offset 处开始, only 为 false 表示同一个 Query 里还有其它语句
+    async fn execute_statement(&self, source: &str, offset: usize, statement: &str, only: bool) -> PgWireResult<PortalResult> {
+        let (query, source_map) = rewrite_query(statement);
+        // COPY 的数据要等这个 Query 处理完之后才会发送, 后面的语句会一直等待工作线程
+        if !only && parse_copy_from_stdin(&query).is_some() {
+            return Err(unsupported_error("COPY FROM STDIN must be the only statement in a query".to_owned()));
+        }
+        self.run_statement(query.clone(), None, vec![], Format::UnifiedText)
+            .await
+            .map_err(|e| map_error_position(e, &query, source, &source_map.with_offset(offset)))
+    }
+
+    // 检查并维护会话的事务状态后执行一条语句
+    async fn run_statement(
+        &self,
+        query: String,
+        name: Option<String>,
+        params: Vec<Box<dyn ToSql + Send>>,
+        format: Format,
+    ) -> PgWireResult<PortalResult> {
+        let (_, kind) = match_execute_type(&query);
+        let query = self.check_transaction_status(&kind, query)?;
+        self.release_worker(matches!(kind.keyword.as_str(), "COMMIT" | "END" | "ROLLBACK" | "ABORT")).await?;
+        self.sync_pg_catalog().await?;
+        let (execute_type, kind) = match_execute_type(&query);
+        if let Some(statement) = parse_copy_from_stdin(&query) {
+            let result = self.start_copy_in(statement?).await;
+            self.update_transaction_status(&kind, result.is_ok());
+            return result;
+        }
+        let variable = matches!(kind.keyword.as_str(), "SET" | "RESET")
+            .then(|| parse_set_variable(&query))
+            .flatten();
+        let result = match (execute_type, variable) {
+            // DuckDB 没有 IntervalStyle, 由服务端自己维护
+            (_, Some(variable)) if variable.name == "intervalstyle" => {
+                self.set_interval_style(variable.value.as_deref())
+                    .map(|_| PortalResult::Execution(Tag::new(&kind.tag)))
+            }
+            (ExecuteType::QUERY(_), _) => {
+                self.query_rows(query, name, params, format, &kind)
+                    .await
+                    .map(PortalResult::Rows)
+            }
+            (ExecuteType::EXECUTE, _) => {
+                self.conn.run_cached(move |statements| {
+                    let stmt = &mut statements
+                        .prepare(name.as_deref(), &query)
+                        .map_err(|e| into_pg_error(e, &query))?
+                        .statement;
+                    let params_ref = params
+                        .iter()
+                        .map(|f| f.as_ref() as &dyn duckdb::ToSql)
+                        .collect::<Vec<&dyn duckdb::ToSql>>();
+                    stmt.execute::<&[&dyn duckdb::ToSql]>(params_ref.as_ref())
+                        .map_err(|e| into_pg_error(e, &query))
+                })
+                .await
+                .and_then(|result| result)
+                .map(|row_modify| {
+                    let tag = execution_tag(&kind, row_modify);
+                    match kind.keyword.as_str() {
+                        "BEGIN" | "START" => PortalResult::TransactionStart(tag),
+                        "COMMIT" | "END" | "ROLLBACK" | "ABORT" => PortalResult::TransactionEnd(tag),
+                        _ => PortalResult::Execution(tag),
+                    }
+                })
+            }
+        };
+        self.update_transaction_status(&kind, result.is_ok());
+        if result.is_ok() && matches!(kind.keyword.as_str(), "SET" | "RESET") {
+            self.refresh_time_zone().await;
+        }
+        if result.is_ok() && matches!(kind.keyword.as_str(), "CREATE" | "DROP" | "ALTER" | "ATTACH" | "DETACH" | "USE") {
+            bump_catalog_version();
+            *self.catalog_changed.lock().unwrap() = true;
+        }
+        result
+    }
+
+    fn session_settings(&self) -> SessionSettings {
+        *self.session_settings.lock().unwrap()
+    }
+
+    // SET / RESET 可能修改了 TimeZone, 重新从 DuckDB 读取
+    async fn refresh_time_zone(&self) {
+        if let Ok(time_zone) = self.conn.run(current_time_zone).await {
+            self.session_settings.lock().unwrap().time_zone = time_zone;
+        }
+    }
+
+    // SET / RESET 修改了 TimeZone 或 IntervalStyle 之后, 和 PostgreSQL 一样通过 ParameterStatus 通知 client
+    async fn send_parameter_status<C>(&self, client: &mut C) -> PgWireResult<()>
+    where
+        C: Sink<PgWireBackendMessage> + Unpin + Send,
+        PgWireError: From<<C as Sink<PgWireBackendMessage>>::Error>,
+    {
+        let settings = self.session_settings();
+        let reported = std::mem::replace(&mut *self.reported_settings.lock().unwrap(), settings);
+        if settings.time_zone != reported.time_zone {
+            let status = ParameterStatus::new("TimeZone".to_owned(), settings.time_zone.name().to_owned());
+            client.feed(PgWireBackendMessage::ParameterStatus(status)).await?;
+        }
+        if settings.interval_style != reported.interval_style {
+            let status = ParameterStatus::new("IntervalStyle".to_owned(), settings.interval_style.name().to_owned());
+            client.feed(PgWireBackendMessage::ParameterStatus(status)).await?;
+        }
+        Ok(())
+    }
+
+    fn pg_enums(&self) -> Arc<Vec<PgEnum>> {
+        self.pg_enums.lock().unwrap().clone()
+    }
+
+    // 执行时的 bump 只让本会话立即看到新的表结构. 其它会话可能在提交之前按旧的表结构重新缓存了语句,
+    // 所以执行过 DDL 的事务(或者自动提交的 DDL)结束后再 bump 一次
+    fn publish_catalog_change(&self) {
+        if self.transaction_status() == TransactionStatus::Idle
+            && std::mem::take(&mut *self.catalog_changed.lock().unwrap())
+        {
+            bump_catalog_version();
+        }
+    }
+
+    // 任意会话的 DDL 都可能创建或删除 ENUM 类型. catalog 版本变化后, 在本会话执行语句之前重新生成 pg_type / pg_enum.
+    // 失败的事务中无法执行, 等事务结束后再刷新
+    pub async fn sync_pg_catalog(&self) -> PgWireResult<()> {
+        let version = catalog_version();
+        if *self.pg_catalog_version.lock().unwrap() == Some(version)
+            || self.transaction_status() == TransactionStatus::Error
+        {
+            return Ok(());
         }
+        let enums = self.conn.run(refresh_pg_catalog).await??;
+        *self.pg_enums.lock().unwrap() = Arc::new(enums);
+        *self.pg_catalog_version.lock().unwrap() = Some(version);
+        Ok(())
     }
+
+    fn set_interval_style(&self, value: Option<&str>) -> PgWireResult<()> {
+        let interval_style = match value {
+            Some(value) => IntervalStyle::parse(value).ok_or_else(|| {
+                PgWireError::UserError(Box::new(ErrorInfo::new(
+                    "ERROR".to_owned(),
+                    "22023".to_owned(),
+                    format!("invalid value for parameter \"IntervalStyle\": \"{}\"", value),
+                )))
+            })?,
+            None => IntervalStyle::Postgres,
+        };
+        self.session_settings.lock().unwrap().interval_style = interval_style;
+        Ok(())
+    }
+
+    pub fn transaction_status(&self) -> TransactionStatus {
+        *self.transaction_status.lock().unwrap()
+    }
+
+    // 和 PostgreSQL 一样, 失败的事务中只允许结束事务, COMMIT 会被当作 ROLLBACK 执行
+    fn check_transaction_status(&self, kind: &StatementKind, query: String) -> PgWireResult<String> {
+        if self.transaction_status() != TransactionStatus::Error {
+            return Ok(query);
+        }
+        match kind.keyword.as_str() {
+            "ROLLBACK" | "ABORT" => Ok(query),
+            "COMMIT" | "END" => Ok("ROLLBACK".to_owned()),
+            _ => Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+                "ERROR".to_owned(),
+                "25P02".to_owned(),
+                "current transaction is aborted, commands ignored until end of transaction block".to_owned(),
+            )))),
+        }
+    }
+
+    fn update_transaction_status(&self, kind: &StatementKind, succeeded: bool) {
+        let mut transaction_status = self.transaction_status.lock().unwrap();
+        *transaction_status = match (kind.keyword.as_str(), succeeded) {
+            ("BEGIN" | "START", true) => TransactionStatus::Transaction,
+            ("COMMIT" | "END" | "ROLLBACK" | "ABORT", true) => TransactionStatus::Idle,
+            (_, false) if *transaction_status != TransactionStatus::Idle => TransactionStatus::Error,
+            _ => *transaction_status,
+        };
+        if matches!(kind.keyword.as_str(), "ROLLBACK" | "ABORT") || (kind.is_transaction_control() && !succeeded) {
+            self.invalidate_pg_catalog();
+        }
+    }
+
+    // 事务中刷新的 pg_type / pg_enum 临时视图和读到的 ENUM 会随事务一起回滚, 下一条语句执行前重新生成
+    fn invalidate_pg_catalog(&self) {
+        *self.pg_catalog_version.lock().unwrap() = None;
+    }
+
+    // 结果集在发送过程中出错, 同样使事务进入失败状态
+    fn fail_transaction(&self) {
+        let mut transaction_status = self.transaction_status.lock().unwrap();
+        if *transaction_status == TransactionStatus::Transaction {
+            *transaction_status = TransactionStatus::Error;
+        }
+    }
+
+    // 和 PostgreSQL 一样, 一个 Query 里的多条语句在隐式事务中执行, 遇到错误时回滚并停止执行后面的语句.
+    // 语句里自带事务控制或者会话已经处于事务中时, 不再额外开启事务.
+    // 每条语句的结果发送完之后才执行下一条, 结果集不在服务端缓存
+    async fn execute_statements<C>(&self, client: &mut C, source: &str, statements: Vec<(usize, &str)>) -> PgWireResult<()>
+    where
+        C: ClientInfo + Sink<PgWireBackendMessage> + Unpin + Send + Sync,
+        C::Error: Debug,
+        PgWireError: From<<C as Sink<PgWireBackendMessage>>::Error>,
+    {
+        let only = statements.len() == 1;
+        let has_transaction_control = statements
+            .iter()
+            .any(|(_, statement)| classify_statement(statement).is_transaction_control());
+        let implicit_transaction = !only
+            && !has_transaction_control
+            && self.transaction_status() == TransactionStatus::Idle;
+        if implicit_transaction {
+            if let Err(e) = self.execute_batch("BEGIN TRANSACTION").await {
+                return send_error(client, e).await;
+            }
+        }
+
+        for (offset, statement) in statements {
+            let result = self.execute_statement(source, offset, statement, only).await;
+            if !self.send_statement_result(client, result).await? {
+                if implicit_transaction {
+                    let _ = self.execute_batch("ROLLBACK").await;
+                    self.invalidate_pg_catalog();
+                }
+                return Ok(());
+            }
+        }
+
+        if implicit_transaction {
+            if let Err(e) = self.execute_batch("COMMIT").await {
+                self.invalidate_pg_catalog();
+                return send_error(client, e).await;
+            }
+        }
+        Ok(())
+    }
+
+    // 发送一条语句的结果, 数据行边从工作线程读取边发送. 返回 false 表示语句失败, 错误已经发给 client
+    async fn send_statement_result<C>(&self, client: &mut C, result: PgWireResult<PortalResult>) -> PgWireResult<bool>
+    where
+        C: ClientInfo + Sink<PgWireBackendMessage> + Unpin + Send + Sync,
+        C::Error: Debug,
+        PgWireError: From<<C as Sink<PgWireBackendMessage>>::Error>,
+    {
+        let mut cursor = match result {
+            Ok(PortalResult::Rows(cursor)) => cursor,
+            Ok(PortalResult::Execution(tag) | PortalResult::TransactionStart(tag) | PortalResult::TransactionEnd(tag)) => {
+                client.feed(PgWireBackendMessage::CommandComplete(tag.into())).await?;
+                return Ok(true);
+            }
+            Ok(PortalResult::CopyIn(format, columns)) => {
+                send_copy_in_response(client, copy_in_response(format, columns)).await?;
+                client.set_state(PgWireConnectionState::CopyInProgress(false));
+                return Ok(true);
+            }
+            Err(e) => {
+                send_error(client, e).await?;
+                return Ok(false);
+            }
+        };
+        let fields = cursor.header.iter().map(Into::into).collect();
+        client.feed(PgWireBackendMessage::RowDescription(RowDescription::new(fields))).await?;
+        let mut rows = 0;
+        while let Some(row) = cursor.next_row().await {
+            match row {
+                Ok(row) => {
+                    client.feed(PgWireBackendMessage::DataRow(row)).await?;
+                    rows += 1;
+                }
+                Err(e) => {
+                    self.fail_transaction();
+                    send_error(client, e).await?;
+                    return Ok(false);
+                }
+            }
+        }
+        let tag = cursor.tag(rows);
+        client.feed(PgWireBackendMessage::CommandComplete(tag.into())).await?;
+        Ok(true)
+    }
+
+    // 隐式事务只在会话空闲时开启, 这时没有打开的 portal
+    async fn execute_batch(&self, query: &'static str) -> PgWireResult<()> {
+        self.release_worker(false).await?;
+        self.conn.run(move |conn| {
+            conn.execute_batch(query)
+                .map_err(|e| into_pg_error(e, query))
+        }).await?
+    }
+
+    // 从 LIMIT 0 子查询的 Arrow schema 推断结果列, 不执行语句本身. INSERT ... RETURNING, PRAGMA 和 CALL 换成结果列相同的 SELECT,
+    // EXPLAIN 的结果列是固定的. 语句执行过时直接使用缓存的结果列(ENUM 已经解析). 不返回结果集的语句没有结果列
+    async fn describe_fields(&self, query: &str, name: Option<String>, format: Format) -> PgWireResult<Vec<FieldInfo>> {
+        let fields = match match_execute_type(query) {
+            (ExecuteType::QUERY(DescribeType::DYNAMIC), _) => {
+                self.release_worker(false).await?;
+                self.sync_pg_catalog().await?;
+                let query = query.to_owned();
+                let enums = self.pg_enums();
+                self.conn.run_cached(move |statements| {
+                    let conn = statements.connection();
+                    let cached = statements
+                        .prepare(name.as_deref(), &query)
+                        .map_err(|e| into_pg_error(e, &query))?;
+                    if let Some(fields) = &cached.fields {
+                        return Ok(describe_row_desc(fields, &format));
+                    }
+                    let probe = describe_query(&query).unwrap_or_else(|| query.clone());
+                    let schema = probe_schema(conn, &probe, None).map_err(|e| into_pg_error(e, &query))?;
+                    let header = row_desc_from_schema(&schema, &format)?;
+                    let fields = resolve_declared_types(conn, &probe, None, &schema, header, &enums)?;
+                    cached.fields = Some(fields.clone());
+                    Ok(fields.as_ref().clone())
+                }).await.and_then(|result| result)
+            }
+            (ExecuteType::QUERY(DescribeType::CONST(fields)), _) => Ok(describe_row_desc(fields, &format)),
+            (ExecuteType::EXECUTE, _) => Ok(vec![]),
+        };
+        // 和执行失败一样, 语句无法 PREPARE 时事务进入失败状态
+        fields.inspect_err(|_| self.fail_transaction())
+    }
+
+    // 同一个连接上 DuckDB 只能有一个活跃的结果集, 挂起的 portal 会让工作线程一直阻塞在它的结果集上.
+    // 在工作线程上执行新的语句前, 把挂起的 portal 剩下的行全部读到内存里, 之后仍然可以从原来的位置继续 Execute;
+    // 结束事务的语句(ends_transaction)和 PostgreSQL 一样先关闭所有 portal, 不再读取它们的结果.
+    // 没有结束的 COPY 同样占用着工作线程, 这时报告错误, 不丢弃已经收到的数据
+    async fn release_worker(&self, ends_transaction: bool) -> PgWireResult<()> {
+        if self.copy_in.lock().unwrap().is_some() {
+            return Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+                "ERROR".to_owned(),
+                "08P01".to_owned(),
+                "COPY FROM STDIN is still in progress".to_owned(),
+            ))));
+        }
+        if ends_transaction {
+            self.close_portals();
+            return Ok(());
+        }
+        let mut portals = std::mem::take(&mut *self.portals.lock().unwrap());
+        for (_, state) in portals.values_mut() {
+            if let PortalState::Open(cursor) = state {
+                cursor.detach().await;
+            }
+        }
+        self.portals.lock().unwrap().extend(portals);
+        Ok(())
+    }
+
+    // 丢弃游标后, 工作线程发送下一行时失败, 随即结束这个结果集
+    fn close_portals(&self) {
+        for (_, state) in self.portals.lock().unwrap().values_mut() {
+            *state = PortalState::Closed;
+        }
+    }
+
+    // 已经执行过的 portal 的结果列, Describe 时不需要工作线程
+    fn open_portal_header(&self, portal: &Portal<FatherDuckStatement>) -> Option<Arc<Vec<FieldInfo>>> {
+        self.portals.lock().unwrap().values().find_map(|(executed, state)| match state {
+            PortalState::Open(cursor) if std::ptr::eq(Arc::as_ptr(executed), portal) => Some(cursor.header.clone()),
+            _ => None,
+        })
+    }
+
+    // 没有处于事务中时 COPY 在自己的事务中执行
+    async fn start_copy_in(&self, statement: CopyStatement) -> PgWireResult<PortalResult> {
+        let format = statement.options.field_format();
+        let implicit_transaction = self.transaction_status() == TransactionStatus::Idle;
+        let (copy_in, columns) = spawn_copy_in(&self.conn, statement, implicit_transaction).await?;
+        *self.copy_in.lock().unwrap() = Some(copy_in);
+        Ok(PortalResult::CopyIn(format, columns))
+    }
+
+    fn take_portal_state(&self, name: &str, portal: &Arc<Portal<FatherDuckStatement>>) -> Option<PortalState> {
+        let mut portals = self.portals.lock().unwrap();
+        match portals.remove(name) {
+            // 同名 portal 被重新 Bind 过, 旧的结果集作废
+            Some((executed, state)) if Arc::ptr_eq(&executed, portal) => Some(state),
+            _ => None,
+        }
+    }
+
+    fn keep_portal_state(&self, name: String, portal: Arc<Portal<FatherDuckStatement>>, state: PortalState) {
+        self.portals.lock().unwrap().insert(name, (portal, state));
+    }
+}
+
+// 一个正在工作线程上产出数据的查询结果
+struct PortalCursor {
+    header: Arc<Vec<FieldInfo>>,
+    rows: mpsc::Receiver<PgWireResult<DataRow>>,
+    // detach 之后从工作线程读出来、还没有发送的行
+    buffered: VecDeque<PgWireResult<DataRow>>,
+    command_tag: String,
+    // EXPLAIN / SHOW 之类的语句只报告命令名, 不带行数
+    with_rows: bool,
+}
+
+impl PortalCursor {
+    async fn next_row(&mut self) -> Option<PgWireResult<DataRow>> {
+        match self.buffered.pop_front() {
+            Some(row) => Some(row),
+            None => self.rows.recv().await,
+        }
+    }
+
+    // 读出剩下的所有行, 让工作线程结束这个结果集
+    async fn detach(&mut self) {
+        while let Some(row) = self.rows.recv().await {
+            self.buffered.push_back(row);
+        }
+    }
+
+    fn tag(&self, rows: usize) -> Tag {
+        let tag = Tag::new(&self.command_tag);
+        if self.with_rows {
+            tag.with_rows(rows)
+        } else {
+            tag
+        }
+    }
+
+    // pgwire 发送 QueryResponse 时总会追加行数, 只有 on_query / on_execute 之外的调用才会用到
+    fn into_response(self) -> Response<'static> {
+        let command_tag = self.command_tag.clone();
+        let mut response = QueryResponse::new(self.header.clone(), self.into_stream());
+        response.set_command_tag(&command_tag);
+        Response::Query(response)
+    }
+
+    // QueryResponse 要求数据流是 Unpin 的
+    fn into_stream(self) -> Pin<Box<dyn Stream<Item = PgWireResult<DataRow>> + Send>> {
+        Box::pin(stream::unfold(self, |mut cursor| async move {
+            cursor.next_row().await.map(|row| (row, cursor))
+        }))
+    }
+}
+
+enum PortalState {
+    Open(PortalCursor),
+    // 发送结果集的过程中出错, 不能继续执行
+    Failed,
+    // 所在的事务已经结束
+    Closed,
+}
+
+enum PortalResult {
+    Rows(PortalCursor),
+    Execution(Tag),
+    TransactionStart(Tag),
+    TransactionEnd(Tag),
+    // COPY FROM STDIN 的数据格式和列数
+    CopyIn(FieldFormat, usize),
 }
 
+impl PortalResult {
+    fn into_response(self) -> Response<'static> {
+        match self {
+            PortalResult::Rows(cursor) => cursor.into_response(),
+            PortalResult::Execution(tag) => Response::Execution(tag),
+            PortalResult::TransactionStart(tag) => Response::TransactionStart(tag),
+            PortalResult::TransactionEnd(tag) => Response::TransactionEnd(tag),
+            PortalResult::CopyIn(format, columns) => Response::CopyIn(copy_in_response(format, columns)),
+        }
+    }
+}
+
+// 流式或物化执行的结果, 都按 RecordBatch 逐批读取
+type RecordBatches<'stmt> = Box<dyn Iterator<Item = RecordBatch> + 'stmt>;
+
+// 工作线程最多领先 client 这么多行, 超过后阻塞等待 socket 消费
+const ROW_CHANNEL_CAPACITY: usize = 1024;
+
 enum ExecuteType {
     QUERY(DescribeType),
     EXECUTE,
@@ -50,566 +666,905 @@ enum ExecuteType {
 
 enum DescribeType {
     DYNAMIC,
-    CONST(Vec<FieldInfo>),
+    CONST(&'static Vec<FieldInfo>),
 }
 
-// https://www.postgresql.org/docs/current/protocol-message-formats.html
 lazy_static! {
-    static ref EXECUTE_TPYE: Vec<(Regex, ExecuteType, String, Option<u32>)> = vec![
-        // QUERY
-        (Regex::new(r"^(?i)SELECT").unwrap(), ExecuteType::QUERY(DescribeType::DYNAMIC), "".to_owned(), None),
-        (Regex::new(r"^(?i)DESCRIBE\s+(\w+)").unwrap(), ExecuteType::QUERY(DescribeType::CONST(vec![
-            FieldInfo::new("column_name".to_string(), None, None, Type::VARCHAR, FieldFormat::Text),
-            FieldInfo::new("column_type".to_string(), None, None, Type::VARCHAR, FieldFormat::Text),
-            FieldInfo::new("null".to_string(), None, None, Type::VARCHAR, FieldFormat::Text),
-            FieldInfo::new("key".to_string(), None, None, Type::VARCHAR, FieldFormat::Text),
-            FieldInfo::new("default".to_string(), None, None, Type::VARCHAR, FieldFormat::Text),
-            FieldInfo::new("extra".to_string(), None, None, Type::VARCHAR, FieldFormat::Text),
-        ])), "".to_owned(), None),
-        (Regex::new(r"^(?i)SHOW\s+DATABASES").unwrap(), ExecuteType::QUERY(DescribeType::CONST(vec![
-            FieldInfo::new("database_name".to_string(), None, None, Type::VARCHAR, FieldFormat::Text),
-        ])), "".to_owned(), None),
-        (Regex::new(r"^(?i)SHOW\s+TABLES").unwrap(), ExecuteType::QUERY(DescribeType::CONST(vec![
-            FieldInfo::new("name".to_string(), None, None, Type::VARCHAR, FieldFormat::Text),
-        ])), "".to_owned(), None),
-        (Regex::new(r"^(?i)UNPIVOT|PIVOT_LONGER\s+").unwrap(), ExecuteType::QUERY(DescribeType::DYNAMIC), "".to_owned(), None),
-
-        // EXECUTE
-        (Regex::new(r"^(?i)INSERT\s+").unwrap(), ExecuteType::EXECUTE, "INSERT".to_owned(), Some(0)),
-        (Regex::new(r"^(?i)UPDATE\s+").unwrap(), ExecuteType::EXECUTE, "UPDATE".to_owned(), None),
-        (Regex::new(r"^(?i)DELETE\s+").unwrap(), ExecuteType::EXECUTE, "DELETE".to_owned(), None),
-        (Regex::new(r"^(?i)TRUNCATE\s+").unwrap(), ExecuteType::EXECUTE, "TRUNCATE".to_owned(), None),
-        (Regex::new(r"^(?i)CREATE\s+(OR\s+REPLACE\s+)?(TEMP(?:ORARY)?\s+)?TABLE|VIEW|MACRO|FUNCTION|SEQUENCE\s+").unwrap(), ExecuteType::EXECUTE, "CREATE".to_owned(), None),
-        (Regex::new(r"^(?i)CREATE\s+SCHEMA\s+(IF\s+NOT\s+EXISTS)?").unwrap(), ExecuteType::EXECUTE, "CREATE".to_owned(), None),
-        (Regex::new(r"^(?i)CREATE\s+(UNIQUE\s+)?INDEX\s+").unwrap(), ExecuteType::EXECUTE, "CREATE".to_owned(), None),
-        (Regex::new(r"^(?i)CREATE\s+TYPE\s+").unwrap(), ExecuteType::EXECUTE, "CREATE".to_owned(), None),
-
-        (Regex::new(r"^(?i)DROP\s+TABLE|INDEX|SEQUENCE\s+").unwrap(), ExecuteType::EXECUTE, "DROP".to_owned(), None),
-        (Regex::new(r"^(?i)ALTER\s+TABLE|VIEW\s+").unwrap(), ExecuteType::EXECUTE, "ALTER".to_owned(), None),
-
-        (Regex::new(r"^(?i)BEGIN\s+TRANSACTION").unwrap(), ExecuteType::EXECUTE, "".to_owned(), None),
-        (Regex::new(r"^(?i)COMMIT|ROLLBACK|ABORT").unwrap(), ExecuteType::EXECUTE, "".to_owned(), None),
-
-        (Regex::new(r"^(?i)DETACH|ATTACH|USE\s+").unwrap(), ExecuteType::EXECUTE, "".to_owned(), None),
-        (Regex::new(r"^(?i)SET|RESET\s+").unwrap(), ExecuteType::EXECUTE, "".to_owned(), None),
-
-        (Regex::new(r"^(?i)ANALYZE").unwrap(), ExecuteType::EXECUTE, "".to_owned(), None),
-        (Regex::new(r"^(?i)CALL\s+").unwrap(), ExecuteType::EXECUTE, "".to_owned(), None),
-        (Regex::new(r"^(?i)(FORCE\s+)?CHECKPOINT\s*").unwrap(), ExecuteType::EXECUTE, "".to_owned(), None),
-        (Regex::new(r"^(?i)COMMENT\s+ON\s+").unwrap(), ExecuteType::EXECUTE, "".to_owned(), None),
-        (Regex::new(r"^(?i)VACUUM").unwrap(), ExecuteType::EXECUTE, "".to_owned(), None),
-
-        (Regex::new(r"^.*").unwrap(), ExecuteType::QUERY(DescribeType::DYNAMIC), "".to_owned(), None),
+    // DuckDB 不支持对这些语句再执行 DESCRIBE, 结果列是固定的
+    static ref DESCRIBE_FIELDS: Vec<FieldInfo> = vec![
+        FieldInfo::new("column_name".to_string(), None, None, Type::VARCHAR, FieldFormat::Text),
+        FieldInfo::new("column_type".to_string(), None, None, Type::VARCHAR, FieldFormat::Text),
+        FieldInfo::new("null".to_string(), None, None, Type::VARCHAR, FieldFormat::Text),
+        FieldInfo::new("key".to_string(), None, None, Type::VARCHAR, FieldFormat::Text),
+        FieldInfo::new("default".to_string(), None, None, Type::VARCHAR, FieldFormat::Text),
+        FieldInfo::new("extra".to_string(), None, None, Type::VARCHAR, FieldFormat::Text),
     ];
+    static ref EXPLAIN_FIELDS: Vec<FieldInfo> = vec![
+        FieldInfo::new("explain_key".to_string(), None, None, Type::VARCHAR, FieldFormat::Text),
+        FieldInfo::new("explain_value".to_string(), None, None, Type::VARCHAR, FieldFormat::Text),
+    ];
+    static ref SHOW_DATABASES_FIELDS: Vec<FieldInfo> = vec![
+        FieldInfo::new("database_name".to_string(), None, None, Type::VARCHAR, FieldFormat::Text),
+    ];
+    static ref SHOW_TABLES_FIELDS: Vec<FieldInfo> = vec![
+        FieldInfo::new("name".to_string(), None, None, Type::VARCHAR, FieldFormat::Text),
+    ];
+}
+
+fn match_execute_type(query: &str) -> (ExecuteType, StatementKind) {
+    let tokens = tokenize(query);
+    let kind = classify_tokens(&tokens);
+    if !kind.returns_rows {
+        return (ExecuteType::EXECUTE, kind);
+    }
+    let second_word = match tokens.get(1).map(|token| &token.kind) {
+        Some(TokenKind::Word(word)) => word.as_str(),
+        _ => "",
+    };
+    let describe_type = match (kind.keyword.as_str(), second_word) {
+        ("DESCRIBE", _) => DescribeType::CONST(&DESCRIBE_FIELDS),
+        ("EXPLAIN", _) => DescribeType::CONST(&EXPLAIN_FIELDS),
+        ("SHOW", "DATABASES") => DescribeType::CONST(&SHOW_DATABASES_FIELDS),
+        ("SHOW", "TABLES") => DescribeType::CONST(&SHOW_TABLES_FIELDS),
+        _ => DescribeType::DYNAMIC,
+    };
+    (ExecuteType::QUERY(describe_type), kind)
+}
+
+// INSERT 的 tag 需要带 oid, 固定为 0
+fn execution_tag(kind: &StatementKind, row_modify: usize) -> Tag {
+    let tag = Tag::new(&kind.tag);
+    let tag = if kind.keyword == "INSERT" { tag.with_oid(0) } else { tag };
+    if kind.with_rows {
+        tag.with_rows(row_modify)
+    } else {
+        tag
+    }
+}
+
+// 返回结果集的语句, with_rows 为 true 时行数在发送完数据后追加
+fn query_command_tag(kind: &StatementKind) -> String {
+    if kind.keyword == "INSERT" && kind.with_rows {
+        format!("{} 0", kind.tag)
+    } else {
+        kind.tag.clone()
+    }
 }
 
 #[async_trait]
 impl SimpleQueryHandler for FatherDuckQueryHandler {
+    // pgwire 默认的 on_query 要等 do_query 返回所有语句的结果才开始发送, 多条语句时前面的结果集只能先缓存起来.
+    // 这里每条语句执行后立即发送它的结果
+    async fn on_query<C>(&self, client: &mut C, query: Query) -> PgWireResult<()>
+    where
+        C: ClientInfo + Sink<PgWireBackendMessage> + Unpin + Send + Sync,
+        C::Error: Debug,
+        PgWireError: From<<C as Sink<PgWireBackendMessage>>::Error>,
+    {
+        if !matches!(client.state(), PgWireConnectionState::ReadyForQuery) {
+            return Err(PgWireError::NotReadyForQuery);
+        }
+        client.set_state(PgWireConnectionState::QueryInProgress);
+        let statements = split_statements(&query.query);
+        if statements.is_empty() {
+            client.feed(PgWireBackendMessage::EmptyQueryResponse(EmptyQueryResponse::new())).await?;
+        } else {
+            self.execute_statements(client, &query.query, statements).await?;
+            self.publish_catalog_change();
+        }
+        client.set_transaction_status(self.transaction_status());
+        self.send_parameter_status(client).await?;
+        // COPY FROM STDIN 在 CopyDone / CopyFail 之后才发送 ReadyForQuery
+        if !matches!(client.state(), PgWireConnectionState::CopyInProgress(_)) {
+            client.set_state(PgWireConnectionState::ReadyForQuery);
+            let ready = ReadyForQuery::new(self.transaction_status());
+            client.send(PgWireBackendMessage::ReadyForQuery(ready)).await?;
+        }
+        Ok(())
+    }
+
+    // 只在 on_query 之外被直接调用, 不支持一次执行多条语句
     async fn do_query<'a, C>(
         &self,
-        _client: &mut C,
+        client: &mut C,
         query: &'a str,
     ) -> PgWireResult<Vec<Response<'a>>>
     where
         C: ClientInfo + Unpin + Send + Sync,
     {
-        println!("SimpleQueryHandler.do_query: {}", query);
-        let conn = self.conn.get();
-        let query = rewrite_query(query);
-
-        let match_execute_type = EXECUTE_TPYE.iter()
-                    .find(|(re, _, _, _)| re.is_match(&query).unwrap());
-        match match_execute_type {
-            Some((re, execute_type, execute_tag, oid)) => {
-                println!("match re: {:?}", re);
-                match execute_type {
-                    ExecuteType::QUERY(_) => {
-                        let mut stmt = conn
-                            .prepare(&query)
-                            .map_err(|e| PgWireError::ApiError(Box::new(e)))?;
-                        
-                        stmt.query(params![])
-                            .map(|rows| {
-                                let header = Arc::new(row_desc_from_stmt(rows.as_ref().unwrap(), &Format::UnifiedText).unwrap());
-                                let s = encode_row_data(rows, header.clone());
-                                vec![Response::Query(QueryResponse::new(header.clone(), s))]
-                            })
-                            .map_err(|e| PgWireError::ApiError(Box::new(e)))
-                    }
-                    ExecuteType::EXECUTE => {
-                        conn.execute(&query, params![])
-                            .map(|row_modify| {
-                                match oid {
-                                    Some(oid) => vec![Response::Execution(Tag::new(execute_tag).with_rows(row_modify).with_oid(*oid))],
-                                    None => vec![Response::Execution(Tag::new(execute_tag).with_rows(row_modify))],
-                                }
-                            })
-                            .map_err(|e| PgWireError::ApiError(Box::new(e)))
-                    }
-                }
-            },
-            None => {
-                Err(PgWireError::ApiError(Box::new(
-                        UnknownError::UnknownError(format!("SimpleQueryHandler.do_query No matching query found: {}", query)),
-                    )))
-            },
-        }
+        let statements = split_statements(query);
+        let result = match statements.as_slice() {
+            [] => Ok(vec![Response::EmptyQuery]),
+            [(offset, statement)] => {
+                let result = self.execute_statement(query, *offset, statement, true).await;
+                self.publish_catalog_change();
+                result.map(|result| vec![result.into_response()])
+            }
+            _ => Err(unsupported_error("multiple statements are only supported through the Query message".to_owned())),
+        };
+        client.set_transaction_status(self.transaction_status());
+        result
     }
 }
 
-fn into_pg_type(df_type: &DataType) -> PgWireResult<Type> {
-    Ok(match df_type {
-        DataType::Null => Type::UNKNOWN,
-        DataType::Boolean => Type::BOOL,
-        DataType::Int8 | DataType::UInt8 => Type::CHAR,
-        DataType::Int16 | DataType::UInt16 => Type::INT2,
-        DataType::Int32 | DataType::UInt32 => Type::INT4,
-        DataType::Int64 | DataType::UInt64 => Type::INT8,
-        DataType::Timestamp(_, _) => Type::TIMESTAMP,
-        DataType::Time32(_) | DataType::Time64(_) => Type::TIME,
-        DataType::Date32 | DataType::Date64 => Type::DATE,
-        DataType::Binary => Type::BYTEA,
-        DataType::Float32 => Type::FLOAT4,
-        DataType::Float64 => Type::FLOAT8,
-        DataType::Utf8 => Type::VARCHAR,
-        DataType::Interval(_) => Type::INTERVAL,
-        DataType::Decimal128(_, _) => Type::NUMERIC,
-        DataType::FixedSizeList(field, _) => match field.data_type() {
-            DataType::Boolean => Type::BOOL_ARRAY,
-            DataType::Int8 | DataType::UInt8 => Type::CHAR_ARRAY,
-            DataType::Int16 | DataType::UInt16 => Type::INT2_ARRAY,
-            DataType::Int32 | DataType::UInt32 => Type::INT4_ARRAY,
-            DataType::Int64 | DataType::UInt64 => Type::INT8_ARRAY,
-            DataType::Timestamp(_, _) => Type::TIMESTAMP_ARRAY,
-            DataType::Time32(_) | DataType::Time64(_) => Type::TIME_ARRAY,
-            DataType::Date32 | DataType::Date64 => Type::DATE_ARRAY,
-            DataType::Binary => Type::BYTEA_ARRAY,
-            DataType::Float32 => Type::FLOAT4_ARRAY,
-            DataType::Float64 => Type::FLOAT8_ARRAY,
-            DataType::Utf8 => Type::VARCHAR_ARRAY,
-            list_type => {
-                return Err(PgWireError::UserError(Box::new(ErrorInfo::new(
-                    "ERROR".to_owned(),
-                    "XX000".to_owned(),
-                    format!("Unsupported List Datatype {list_type}"),
-                ))));
-            }
-        }
-        DataType::List(field) => match field.data_type() {
-            DataType::Boolean => Type::BOOL_ARRAY,
-            DataType::Int8 | DataType::UInt8 => Type::CHAR_ARRAY,
-            DataType::Int16 | DataType::UInt16 => Type::INT2_ARRAY,
-            DataType::Int32 | DataType::UInt32 => Type::INT4_ARRAY,
-            DataType::Int64 | DataType::UInt64 => Type::INT8_ARRAY,
-            DataType::Timestamp(_, _) => Type::TIMESTAMP_ARRAY,
-            DataType::Time32(_) | DataType::Time64(_) => Type::TIME_ARRAY,
-            DataType::Date32 | DataType::Date64 => Type::DATE_ARRAY,
-            DataType::Binary => Type::BYTEA_ARRAY,
-            DataType::Float32 => Type::FLOAT4_ARRAY,
-            DataType::Float64 => Type::FLOAT8_ARRAY,
-            DataType::Utf8 => Type::VARCHAR_ARRAY,
-            list_type => {
-                return Err(PgWireError::UserError(Box::new(ErrorInfo::new(
-                    "ERROR".to_owned(),
-                    "XX000".to_owned(),
-                    format!("Unsupported List Datatype {list_type}"),
-                ))));
-            }
-        },
-        _ => {
-            return Err(PgWireError::UserError(Box::new(ErrorInfo::new(
-                "ERROR".to_owned(),
-                "XX000".to_owned(),
-                format!("Unsupported Datatype {df_type}"),
-            ))));
-        }
-    })
+// 数据由 CopyHandler 接收, 所有列使用相同的格式
+fn copy_in_response(format: FieldFormat, columns: usize) -> CopyResponse {
+    CopyResponse::new(format.value() as i8, columns, vec![format.value(); columns])
+}
+
+async fn send_error<C>(client: &mut C, error: PgWireError) -> PgWireResult<()>
+where
+    C: Sink<PgWireBackendMessage> + Unpin + Send,
+    PgWireError: From<<C as Sink<PgWireBackendMessage>>::Error>,
+{
+    client.feed(PgWireBackendMessage::ErrorResponse(into_error_info(error).into())).await?;
+    Ok(())
+}
+
+fn into_error_info(error: PgWireError) -> ErrorInfo {
+    match error {
+        PgWireError::UserError(error_info) => *error_info,
+        other => ErrorInfo::new("ERROR".to_owned(), "XX000".to_owned(), other.to_string()),
+    }
 }
 
-fn row_desc_from_stmt(stmt: &Statement, format: &Format) -> PgWireResult<Vec<FieldInfo>> {
-    let columns = stmt.column_count();
+// 命名语句的预编译语句一直缓存到 Close, 未命名语句返回 None
+fn statement_name(stmt: &StoredStatement<FatherDuckStatement>) -> Option<String> {
+    (stmt.id != DEFAULT_NAME).then(|| stmt.id.clone())
+}
 
-    (0..columns)
-        .map(|idx| {
-            let datatype = stmt.column_type(idx);
-            let name = stmt.column_name(idx).unwrap();
-            println!("datatype: {}", datatype);
-            println!("into_pg_type(&datatype).unwrap(): {}", into_pg_type(&datatype).unwrap());
+// 缓存的结果列按本次 Describe 的格式返回
+fn describe_row_desc(fields: &[FieldInfo], format: &Format) -> Vec<FieldInfo> {
+    fields
+        .iter()
+        .enumerate()
+        .map(|(idx, field)| {
+            FieldInfo::new(
+                field.name().to_owned(),
+                field.table_id(),
+                field.column_id(),
+                field.datatype().clone(),
+                format.format_for(idx),
+            )
+        })
+        .collect()
+}
+
+fn row_desc_from_schema(schema: &Schema, format: &Format) -> PgWireResult<Vec<FieldInfo>> {
+    schema
+        .fields()
+        .iter()
+        .enumerate()
+        .map(|(idx, field)| {
             Ok(FieldInfo::new(
-                name.clone(),
+                field.name().clone(),
                 None,
                 None,
-                into_pg_type(&datatype).unwrap(),
+                into_pg_field_type(field)?,
                 format.format_for(idx),
             ))
         })
         .collect()
 }
 
-const BASE_DATE: NaiveDate = NaiveDate::from_ymd_opt(1970, 1, 1).unwrap();
-
-fn encode_row_data(
-    mut rows: Rows<'_>,
-    schema: Arc<Vec<FieldInfo>>,
-) -> impl Stream<Item = PgWireResult<DataRow>> {
-    let mut results = Vec::new();
-    let ncols = schema.len();
-    while let Ok(Some(row)) = rows.next() {
-        let mut encoder = DataRowEncoder::new(schema.clone());
-        for idx in 0..ncols {            
-            let data = row.get_ref_unwrap::<usize>(idx);
-            match data {
-                ValueRef::Null => encoder.encode_field(&None::<i8>).unwrap(),
-                ValueRef::Boolean(b) => {
-                    if b {
-                        encoder.encode_field(&"true".to_string()).unwrap();
-                    } else {
-                        encoder.encode_field(&"false".to_string()).unwrap();
-                    }
-                    // encoder.encode_field(&b).unwrap();
-                }
-                ValueRef::TinyInt(i) => {
-                    encoder.encode_field(&i).unwrap();
-                }
-                ValueRef::SmallInt(i) => {
-                    encoder.encode_field(&i).unwrap();
-                }
-                ValueRef::Int(i) => {
-                    encoder.encode_field(&i).unwrap();
-                }
-                ValueRef::BigInt(i) => {
-                    encoder.encode_field(&i).unwrap();
-                }
-                ValueRef::UInt(i) => {
-                    encoder.encode_field(&i).unwrap();
-                }
-                // ValueRef::HugeInt(i) => {
-                //     encoder.encode_field(&i).unwrap();
-                // }
-                ValueRef::Float(f) => {
-                    encoder.encode_field(&f).unwrap();
-                }
-                ValueRef::Double(f) => {
-                    encoder.encode_field(&f).unwrap();
-                }
-                ValueRef::Text(t) => {
-                    encoder
-                        .encode_field(&String::from_utf8_lossy(t).as_ref())
-                        .unwrap();
-                }
-                ValueRef::Blob(b) => {
-                    encoder.encode_field(&b).unwrap();
-                }
-                ValueRef::Date32(d) => {
-                    encoder
-                        .encode_field(&(BASE_DATE + Duration::days(d as i64)))
-                        .unwrap();
-                },
-                ValueRef::Time64(timeunit, v) => {
-                    match timeunit {
-                        duckdb::types::TimeUnit::Microsecond => {
-                            let seconds = v / 1_000_000;
-                            let microseconds = (v % 1_000_000) as u32;
-                            let time = NaiveTime::from_num_seconds_from_midnight_opt(seconds as u32, microseconds * 1_000).unwrap();
-                            // time.format("%H:%M:%S%.6f").to_string()
-                            encoder.encode_field(&time).unwrap();
-                        },
-                        _ => {
-                            unimplemented!("timeunit {:?} not supported.", timeunit)
-                        }
-                    }
-                }
-                ValueRef::Timestamp(timeunit, v) => {
-                    match timeunit {
-                        duckdb::types::TimeUnit::Microsecond => {
-                            let seconds = v / 1_000_000;
-                            let microseconds = (v % 1_000_000) as u32;
-                            let datetime = DateTime::from_timestamp(seconds, microseconds * 1_000).unwrap();
-                            encoder.encode_field(&datetime).unwrap();
-                        },
-                        _ => {
-                            unimplemented!("timeunit {:?} not supported.", timeunit)
-                        }
-                    }
-                }
-                ValueRef::Decimal(d) => {
-                    encoder.encode_field(&d).unwrap();
-                },
-                // ValueRef::List(list_type, u) => {
-                //     match list_type {
-                //         duckdb::types::ListType::Regular(generic_list_array) => {
-                //             let a= generic_list_array
-                //                 .value(0)
-                                
-                //             encoder.encode_field(&a).unwrap();
-
-                //         },
-                //         _ => {
-                //             unimplemented!("list_type {:?} not supported.", list_type)
-                //         }
-                //     }
-                //     encoder.encode_field(&list_type).unwrap();
-                // },
-                // ValueRef::Interval { months, days, nanos } => {
-                //     encoder.encode_field(&format!("{} months {} days {} nanos", months, days, nanos)).unwrap();
-                // }
-                // ValueRef::Enum(e, _) => {
-                //     encoder.encode_field(&e).unwrap();
-                //     // (enum_type, row)
-                // }
-
-                other => {
-                    unimplemented!("type {:?} not supported.", other)
-                }
-            }
-        }
+// ENUM 在 Arrow 中是没有类型名的字典类型, 按 DESCRIBE 给出的结果类型找到对应的 ENUM, 找不到时按 VARCHAR 返回.
+// 结果列里有字典类型而且存在 ENUM 时才需要在会话连接上再 PREPARE 一次. DESCRIBE 只绑定语句, 不会执行它;
+// 包在子查询里也保证了不是单条 SELECT 的语句(包括多条语句)无法 PREPARE, 这时同样按 VARCHAR 返回
+fn resolve_declared_types(
+    conn: &Connection,
+    query: &str,
+    params: Option<&[&dyn ToSql]>,
+    schema: &Schema,
+    header: Vec<FieldInfo>,
+    enums: &[PgEnum],
+) -> PgWireResult<Arc<Vec<FieldInfo>>> {
+    let needs_declared_types = !enums.is_empty()
+        && schema.fields().iter().any(|field| matches!(field.data_type(), DataType::Dictionary(_, _)));
+    if !needs_declared_types {
+        return Ok(Arc::new(header));
+    }
+    let describe = format!("DESCRIBE SELECT * FROM (\n{}\n)", subquery_text(query));
+    let result_types = match conn.prepare(&describe).and_then(|mut stmt| {
+        let result_types = stmt
+            .query_map(probe_params(&stmt, params).as_slice(), |row| row.get::<usize, String>(1))?
+            .collect::<Result<Vec<String>, _>>();
+        result_types
+    }) {
+        Ok(result_types) => result_types,
+        Err(_) => return Ok(Arc::new(header)),
+    };
+    let header = header
+        .into_iter()
+        .zip(schema.fields())
+        .zip(result_types)
+        .map(|((field, arrow_field), result_type)| match arrow_field.data_type() {
+            DataType::Dictionary(_, _) => match find_enum_by_type(enums, &result_type) {
+                Some(pg_enum) => FieldInfo::new(
+                    field.name().to_owned(),
+                    field.table_id(),
+                    field.column_id(),
+                    pg_enum.pg_type(),
+                    field.format(),
+                ),
+                None => field,
+            },
+            _ => field,
+        })
+        .collect();
+    Ok(Arc::new(header))
+}
 
-        results.push(encoder.finish());
+// 执行 LIMIT 0 的子查询取得语句结果的 Arrow schema. 常量 LIMIT 0 会被 DuckDB 优化成空结果, 语句本身不会被执行
+fn probe_schema(conn: &Connection, query: &str, params: Option<&[&dyn ToSql]>) -> duckdb::Result<SchemaRef> {
+    let mut stmt = conn.prepare(&format!("SELECT * FROM (\n{}\n) LIMIT 0", subquery_text(query)))?;
+    stmt.execute(probe_params(&stmt, params).as_slice())?;
+    Ok(stmt.schema())
+}
+
+// Describe 时参数还没有绑定(params 为 None), 全部按 NULL 绑定
+fn probe_params<'a>(stmt: &Statement, params: Option<&[&'a dyn ToSql]>) -> Vec<&'a dyn ToSql> {
+    match params {
+        Some(params) => params.to_vec(),
+        None => vec![&Null; stmt.parameter_count()],
     }
+}
 
-    stream::iter(results.into_iter())
+// 作为子查询时去掉语句末尾的分号
+fn subquery_text(query: &str) -> &str {
+    query.trim_end().trim_end_matches(';')
 }
 
-fn get_params(portal: &Portal<String>) -> Vec<Box<dyn ToSql>> {
+// stream_arrow 每次只从 DuckDB 拉取一个 chunk, 编码好的行立即发送, 不在服务端缓存整个结果集.
+// 返回 false 表示编码出错(错误已经发给 client)或者接收端已关闭(client 断开或不再需要后续数据), 停止拉取
+fn encode_batch(
+    batch: &RecordBatch,
+    schema: &Arc<Vec<FieldInfo>>,
+    column_fields: &[FieldRef],
+    settings: SessionSettings,
+    sender: &mpsc::Sender<PgWireResult<DataRow>>,
+) -> bool {
+    for row in 0..batch.num_rows() {
+        let data_row = encode_row(batch, row, schema, column_fields, settings);
+        let failed = data_row.is_err();
+        if sender.blocking_send(data_row).is_err() || failed {
+            return false;
+        }
+    }
+    true
+}
+
+// column_fields 是结果列的 Arrow 字段, 编码 STRUCT / LIST 等嵌套类型时需要里面的字段类型, JSON 需要字段的 metadata
+fn encode_row(
+    batch: &RecordBatch,
+    row: usize,
+    schema: &Arc<Vec<FieldInfo>>,
+    column_fields: &[FieldRef],
+    settings: SessionSettings,
+) -> PgWireResult<DataRow> {
+    let mut encoder = DataRowEncoder::new(schema.clone());
+    for (column, field) in batch.columns().iter().zip(column_fields) {
+        let value = array_value(column.as_ref(), row)?;
+        encoder.encode_field(&PgValue::from_field_value(value, field, &settings)?)?;
+    }
+    encoder.finish()
+}
+
+fn get_params(portal: &Portal<FatherDuckStatement>) -> PgWireResult<Vec<Box<dyn ToSql + Send>>> {
     let mut results = Vec::with_capacity(portal.parameter_len());
     for i in 0..portal.parameter_len() {
-        let param_type = portal.statement.parameter_types.get(i).unwrap();
-        // we only support a small amount of types for demo
+        // 类型未知的参数按文本处理, 交给 DuckDB 做类型转换
+        let param_type = portal.statement.statement.parameter_types.get(i).unwrap_or(&Type::UNKNOWN);
         match param_type {
             &Type::BOOL => {
-                let param = portal.parameter::<bool>(i, param_type).unwrap();
-                results.push(Box::new(param) as Box<dyn ToSql>);
+                let param = portal.parameter::<bool>(i, param_type)?;
+                results.push(Box::new(param) as Box<dyn ToSql + Send>);
             }
             &Type::INT2 => {
-                let param = portal.parameter::<i16>(i, param_type).unwrap();
-                results.push(Box::new(param) as Box<dyn ToSql>);
+                let param = portal.parameter::<i16>(i, param_type)?;
+                results.push(Box::new(param) as Box<dyn ToSql + Send>);
             }
             &Type::INT4 => {
-                let param = portal.parameter::<i32>(i, param_type).unwrap();
-                results.push(Box::new(param) as Box<dyn ToSql>);
+                let param = portal.parameter::<i32>(i, param_type)?;
+                results.push(Box::new(param) as Box<dyn ToSql + Send>);
             }
             &Type::INT8 => {
-                let param = portal.parameter::<i64>(i, param_type).unwrap();
-                results.push(Box::new(param) as Box<dyn ToSql>);
+                let param = portal.parameter::<i64>(i, param_type)?;
+                results.push(Box::new(param) as Box<dyn ToSql + Send>);
             }
-            &Type::TEXT | &Type::VARCHAR => {
-                let param = portal.parameter::<String>(i, param_type).unwrap();
-                results.push(Box::new(param) as Box<dyn ToSql>);
+            &Type::TEXT | &Type::VARCHAR | &Type::BPCHAR | &Type::NAME | &Type::UNKNOWN => {
+                let param = portal.parameter::<String>(i, &Type::TEXT)?;
+                results.push(Box::new(param) as Box<dyn ToSql + Send>);
             }
             &Type::FLOAT4 => {
-                let param = portal.parameter::<f32>(i, param_type).unwrap();
-                results.push(Box::new(param) as Box<dyn ToSql>);
+                let param = portal.parameter::<f32>(i, param_type)?;
+                results.push(Box::new(param) as Box<dyn ToSql + Send>);
             }
             &Type::FLOAT8 => {
-                let param = portal.parameter::<f64>(i, param_type).unwrap();
-                results.push(Box::new(param) as Box<dyn ToSql>);
+                let param = portal.parameter::<f64>(i, param_type)?;
+                results.push(Box::new(param) as Box<dyn ToSql + Send>);
+            }
+            // 时间戳按 ISO 8601 文本绑定, 由 DuckDB 转换
+            &Type::TIMESTAMP => {
+                let param = portal.parameter::<NaiveDateTime>(i, param_type)?;
+                results.push(Box::new(param.map(|p| p.format("%F %T%.f").to_string())) as Box<dyn ToSql + Send>);
+            }
+            &Type::INTERVAL => {
+                let param = if portal.parameter_format.format_for(i) == FieldFormat::Binary {
+                    portal.parameters[i]
+                        .as_deref()
+                        .map(|raw| PgInterval::from_sql(param_type, raw).map(Value::from))
+                        .transpose()
+                        .map_err(|e| invalid_value_error(format!("invalid interval parameter: {}", e)))?
+                } else {
+                    // 文本格式的 interval 交给 DuckDB 解析
+                    portal.parameter::<String>(i, &Type::TEXT)?.map(Value::Text)
+                };
+                results.push(Box::new(param) as Box<dyn ToSql + Send>);
+            }
+            &Type::TIMESTAMPTZ => {
+                let param = portal.parameter::<DateTime<FixedOffset>>(i, param_type)?;
+                let param = param.map(|p| p.with_timezone(&Utc).format("%F %T%.f%:z").to_string());
+                results.push(Box::new(param) as Box<dyn ToSql + Send>);
+            }
+            // 这些类型按文本绑定, 由 DuckDB 转换, 避免在 Rust 里损失精度
+            &Type::CHAR | &Type::NUMERIC | &Type::DATE | &Type::TIME | &Type::TIMETZ | &Type::UUID => {
+                let param = portal.parameters[i]
+                    .as_deref()
+                    .map(|raw| text_param(raw, portal.parameter_format.format_for(i), param_type))
+                    .transpose()?;
+                results.push(Box::new(param) as Box<dyn ToSql + Send>);
+            }
+            &Type::BYTEA => {
+                let param = portal.parameters[i]
+                    .as_deref()
+                    .map(|raw| bytea_param(raw, portal.parameter_format.format_for(i)).map(Value::Blob))
+                    .transpose()?;
+                results.push(Box::new(param) as Box<dyn ToSql + Send>);
+            }
+            &Type::BIT | &Type::VARBIT => {
+                let param = portal.parameters[i]
+                    .as_deref()
+                    .map(|raw| bit_param(raw, portal.parameter_format.format_for(i), param_type))
+                    .transpose()?;
+                results.push(Box::new(param) as Box<dyn ToSql + Send>);
+            }
+            &Type::JSON | &Type::JSONB => {
+                let param = portal.parameters[i]
+                    .as_deref()
+                    .map(|raw| json_param(raw, portal.parameter_format.format_for(i), param_type))
+                    .transpose()?;
+                results.push(Box::new(param) as Box<dyn ToSql + Send>);
+            }
+            _ if matches!(param_type.kind(), Kind::Array(_)) => {
+                let param = portal.parameters[i]
+                    .as_deref()
+                    .map(|raw| array_param_literal(raw, portal.parameter_format.format_for(i), param_type))
+                    .transpose()?;
+                results.push(Box::new(param) as Box<dyn ToSql + Send>);
+            }
+            // ENUM 的二进制格式也是 label 本身, 其它类型只接受文本参数, 同样交给 DuckDB 转换
+            _ if matches!(param_type.kind(), Kind::Enum(_))
+                || portal.parameter_format.format_for(i) == FieldFormat::Text =>
+            {
+                let param = portal.parameter::<String>(i, &Type::TEXT)?;
+                results.push(Box::new(param) as Box<dyn ToSql + Send>);
             }
             _ => {
-                unimplemented!("parameter type not supported")
+                return Err(unsupported_error(format!("parameter type {} is not supported", param_type)));
             }
         }
     }
 
-    results
-}
-
-fn into_arrow_type(df_type: &str) -> PgWireResult<DataType> {
-    let part: Vec<&str> = df_type.split("(").collect();
-    let first_part = part[0];
-    Ok(match first_part {
-        "BIGINT" | "INT8" | "LONG" => DataType::Int64,
-        "BLOB" => DataType::Binary,
-        "BOOLEAN" => DataType::Boolean,
-        "DATE" => DataType::Date32,
-        "DOUBLE" => DataType::Float64,
-        "FLOAT" => DataType::Float32,
-        "INTEGER" => DataType::Int32,
-        "SMALLINT" => DataType::Int16,
-        "TINYINT" => DataType::Int8,
-        "HUGEINT" => DataType::Int64,
-        "UINTEGER" => DataType::UInt32,
-        "VARCHAR" => DataType::Utf8,
-        "TIME" => DataType::Time64(TimeUnit::Microsecond),
-        "TIMESTAMP" => DataType::Timestamp(TimeUnit::Microsecond, None),
-        "UUID" => DataType::Utf8,
-        "DECIMAL" | "NUMERIC" => {
-            let re = Regex::new(r"DECIMAL\((\d+),(\d+)\)").unwrap();
-            if let Ok(Some(caps)) = re.captures(df_type) {
-                let prec: u8 = caps[1].parse().unwrap();
-                let scale: i8 = caps[2].parse().unwrap();
-                DataType::Decimal128(prec, scale)
-            } else {
-                panic!("type DECIMAL err: {}", df_type)
-            }
-        }
-        _ => {
-            return Err(PgWireError::UserError(Box::new(ErrorInfo::new(
-                "ERROR".to_owned(),
-                "XX000".to_owned(),
-                format!("Unsupported Datatype {df_type}"),
-            ))));
-        }
-    })
-}
-
-fn get_field_infos_from_describe(rows: &mut Rows) -> PgWireResult<Vec<FieldInfo>> {
-    let mut result: Vec<FieldInfo> = Vec::new();
-    while let Ok(Some(row)) = rows.next() {
-        let column_name: String = row.get_unwrap(0);
-        let column_type: String = row.get_unwrap(1);
-        let field = FieldInfo::new(
-            column_name,
-            None,
-            None,
-            into_pg_type(&into_arrow_type(&column_type).unwrap()).unwrap(),
-            FieldFormat::Text
-        );
-        result.push(field);
-    }
-    Ok(result)
+    Ok(results)
 }
 
 #[async_trait]
 impl ExtendedQueryHandler for FatherDuckQueryHandler {
-    type Statement = String;
+    type Statement = FatherDuckStatement;
     type QueryParser = FatherDuckQueryParser;
 
     fn query_parser(&self) -> Arc<Self::QueryParser> {
         self.query_parser.clone()
     }
 
+    async fn on_execute<C>(&self, client: &mut C, message: Execute) -> PgWireResult<()>
+    where
+        C: ClientInfo + ClientPortalStore + Sink<PgWireBackendMessage> + Unpin + Send + Sync,
+        C::PortalStore: PortalStore<Statement = Self::Statement>,
+        C::Error: Debug,
+        PgWireError: From<<C as Sink<PgWireBackendMessage>>::Error>,
+    {
+        let portal_name = message.name.as_deref().unwrap_or(DEFAULT_NAME).to_owned();
+        let max_rows = message.max_rows as usize;
+        let portal = client
+            .portal_store()
+            .get_portal(&portal_name)
+            .ok_or_else(|| PgWireError::PortalNotFound(portal_name.clone()))?;
+
+        let mut cursor = match self.take_portal_state(&portal_name, &portal) {
+            Some(PortalState::Open(cursor)) => cursor,
+            Some(PortalState::Failed) => {
+                return Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+                    "ERROR".to_owned(),
+                    "55000".to_owned(),
+                    format!("portal \"{}\" cannot be run", portal_name),
+                ))));
+            }
+            Some(PortalState::Closed) => {
+                return Err(PgWireError::UserError(Box::new(ErrorInfo::new(
+                    "ERROR".to_owned(),
+                    "34000".to_owned(),
+                    format!("portal \"{}\" does not exist", portal_name),
+                ))));
+            }
+            None => {
+                let result = self.execute_portal(portal.as_ref()).await;
+                client.set_transaction_status(self.transaction_status());
+                match result? {
+                    PortalResult::Rows(cursor) => cursor,
+                    PortalResult::Execution(tag)
+                    | PortalResult::TransactionStart(tag)
+                    | PortalResult::TransactionEnd(tag) => {
+                        client.feed(PgWireBackendMessage::CommandComplete(tag.into())).await?;
+                        self.send_parameter_status(client).await?;
+                        client.flush().await?;
+                        return Ok(());
+                    }
+                    PortalResult::CopyIn(format, columns) => {
+                        send_copy_in_response(client, copy_in_response(format, columns)).await?;
+                        client.set_state(PgWireConnectionState::CopyInProgress(true));
+                        return Ok(());
+                    }
+                }
+            }
+        };
+
+        // max_rows 为 0 表示取完所有数据
+        let mut rows = 0;
+        while max_rows == 0 || rows < max_rows {
+            match cursor.next_row().await {
+                Some(Ok(row)) => {
+                    client.feed(PgWireBackendMessage::DataRow(row)).await?;
+                    rows += 1;
+                }
+                Some(Err(e)) => {
+                    self.fail_transaction();
+                    client.set_transaction_status(self.transaction_status());
+                    self.keep_portal_state(portal_name, portal, PortalState::Failed);
+                    return Err(e);
+                }
+                None => {
+                    let tag = cursor.tag(rows);
+                    client.send(PgWireBackendMessage::CommandComplete(tag.into())).await?;
+                    self.keep_portal_state(portal_name, portal, PortalState::Open(cursor));
+                    return Ok(());
+                }
+            }
+        }
+
+        // 达到 max_rows, 保留结果集等待 client 对同一个 portal 再次 Execute
+        client.send(PgWireBackendMessage::PortalSuspended(PortalSuspended::new())).await?;
+        self.keep_portal_state(portal_name, portal, PortalState::Open(cursor));
+        Ok(())
+    }
+
+    // 和 PostgreSQL 一样, Sync 结束隐式事务时关闭所有 portal, 显式事务中的 portal 保留到事务结束
+    async fn on_sync<C>(&self, client: &mut C, _message: PgSync) -> PgWireResult<()>
+    where
+        C: ClientInfo + Sink<PgWireBackendMessage> + Unpin + Send + Sync,
+        C::Error: Debug,
+        PgWireError: From<<C as Sink<PgWireBackendMessage>>::Error>,
+    {
+        if self.transaction_status() == TransactionStatus::Idle {
+            self.close_portals();
+        }
+        let ready = ReadyForQuery::new(self.transaction_status());
+        client.send(PgWireBackendMessage::ReadyForQuery(ready)).await?;
+        Ok(())
+    }
+
+    async fn on_close<C>(&self, client: &mut C, message: Close) -> PgWireResult<()>
+    where
+        C: ClientInfo + ClientPortalStore + Sink<PgWireBackendMessage> + Unpin + Send + Sync,
+        C::PortalStore: PortalStore<Statement = Self::Statement>,
+        C::Error: Debug,
+        PgWireError: From<<C as Sink<PgWireBackendMessage>>::Error>,
+    {
+        let name = message.name.as_deref().unwrap_or(DEFAULT_NAME);
+        match message.target_type {
+            TARGET_TYPE_BYTE_STATEMENT => {
+                client.portal_store().rm_statement(name);
+                // 命名语句关闭后它的预编译语句才可以被淘汰
+                if name != DEFAULT_NAME {
+                    let name = name.to_owned();
+                    self.conn.spawn_cached(move |statements| statements.close(&name))?;
+                }
+            }
+            TARGET_TYPE_BYTE_PORTAL => {
+                client.portal_store().rm_portal(name);
+                self.portals.lock().unwrap().remove(name);
+            }
+            _ => {}
+        }
+        client.send(PgWireBackendMessage::CloseComplete(CloseComplete::new())).await?;
+        Ok(())
+    }
+
+    // on_execute 已经覆盖, 由它处理 max_rows 和 PortalSuspended, pgwire 不会再调用这里
     async fn do_query<'a, C>(
         &self,
         _client: &mut C,
-        portal: &'a Portal<Self::Statement>,
+        _portal: &'a Portal<Self::Statement>,
         _max_rows: usize,
     ) -> PgWireResult<Response<'a>>
     where
         C: ClientInfo + Unpin + Send + Sync,
     {
-        let conn = self.conn.get();
-        let query = &portal.statement.statement;
-        println!("ExtendedQueryHandler.do_query query: {}", query);
-
-        let match_execute_type = EXECUTE_TPYE.iter()
-                    .find(|(re, _, _, _)| re.is_match(&query).unwrap());
-        match match_execute_type {
-            Some((re, execute_type, execute_tag, oid)) => {
-                println!("match re: {:?}", re);
-                let mut stmt = conn
-                    .prepare(query)
-                    .map_err(|e| PgWireError::ApiError(Box::new(e)))?;
-                let params = get_params(portal);
-                let params_ref = params
-                    .iter()
-                    .map(|f| f.as_ref())
-                    .collect::<Vec<&dyn duckdb::ToSql>>();
-                match execute_type {
-                    ExecuteType::QUERY(_) => {
-                        stmt.query::<&[&dyn duckdb::ToSql]>(params_ref.as_ref())
-                        .map(|rows| {
-                            let header = Arc::new(row_desc_from_stmt(rows.as_ref().unwrap(), &portal.result_column_format).unwrap());
-                            let s = encode_row_data(rows, header.clone());
-                            Response::Query(QueryResponse::new(header, s))
-                        })
-                        .map_err(|e| PgWireError::ApiError(Box::new(e)))
-                    }
-                    ExecuteType::EXECUTE => {
-                        stmt.execute::<&[&dyn duckdb::ToSql]>(params_ref.as_ref())
-                            .map(|row_modify| {
-                                match oid {
-                                    Some(oid) => Response::Execution(Tag::new(execute_tag).with_rows(row_modify).with_oid(*oid)),
-                                    None => Response::Execution(Tag::new(execute_tag).with_rows(row_modify)),
-                                }
-                            })
-                            .map_err(|e| PgWireError::ApiError(Box::new(e)))
-                    }
-                }
-            },
-            None => {
-                Err(PgWireError::ApiError(Box::new(
-                        UnknownError::UnknownError(format!("ExtendedQueryHandler.do_query No matching query found: {}", query)),
-                    )))
-            },
-        }
+        unreachable!("extended queries are executed by on_execute")
     }
 
     async fn do_describe_statement<C>(
         &self,
-        _client: &mut C,
-        _stmt: &StoredStatement<Self::Statement>,
+        client: &mut C,
+        stmt: &StoredStatement<Self::Statement>,
     ) -> PgWireResult<DescribeStatementResponse>
     where
         C: ClientInfo + Unpin + Send + Sync,
     {
-        unimplemented!("do_describe_statement");
-        // let conn = self.conn.lock().unwrap();
-        // let param_types = stmt.parameter_types.clone();
-        // let query = &stmt.statement;
-        // println!("ExtendedQueryHandler.do_describe_statement query: {}", query);
-        // let mut stmt = conn
-        //     .prepare_cached(query)
-        //     .map_err(|e| PgWireError::ApiError(Box::new(e)))?;
-        // let _ = stmt.execute([]);
-        // row_desc_from_stmt(&stmt, &Format::UnifiedBinary)
-        //     .map(|fields| DescribeStatementResponse::new(param_types, fields))
+        let query = &stmt.statement.query;
+        let param_types = stmt.statement.parameter_types.clone();
+        let fields = self
+            .describe_fields(query, statement_name(stmt), Format::UnifiedText)
+            .await
+            .map_err(|e| stmt.statement.map_error(e));
+        client.set_transaction_status(self.transaction_status());
+        Ok(DescribeStatementResponse::new(param_types, fields?))
     }
 
     async fn do_describe_portal<C>(
         &self,
-        _client: &mut C,
+        client: &mut C,
         portal: &Portal<Self::Statement>,
     ) -> PgWireResult<DescribePortalResponse>
     where
         C: ClientInfo + Unpin + Send + Sync,
     {
-        let conn = self.conn.get();
-        let query = &portal.statement.statement;
-        println!("ExtendedQueryHandler.do_describe_portal query: {}", query);
-        let match_execute_type = EXECUTE_TPYE.iter()
-                    .find(|(re, _, _, _)| re.is_match(&query).unwrap());
-        match match_execute_type {
-            Some((re, execute_type, _, _)) => {
-                println!("match re: {:?}", re);
-                match execute_type {
-                    ExecuteType::QUERY(describe_type) => {
-                        match describe_type {
-                            DescribeType::DYNAMIC => {
-                                let query = &("DESCRIBE ".to_string() + query);
-                                let mut stmt = conn
-                                    .prepare(query)
-                                    .map_err(|e| PgWireError::ApiError(Box::new(e)))?;
-                                let params = get_params(portal);
-                                let params_ref = params
-                                    .iter()
-                                    .map(|f| f.as_ref())
-                                    .collect::<Vec<&dyn duckdb::ToSql>>();
-                                params_ref.iter().for_each(|f| {
-                                    println!("do_describe_portal params_ref: {:?}", f.to_sql());
-                                });
-    
-                                stmt.query::<&[&dyn duckdb::ToSql]>(params_ref.as_ref())
-                                    .map(|mut rows| {
-                                        let header = get_field_infos_from_describe(&mut rows);
-                                        DescribePortalResponse::new(header.unwrap())
-                                    })
-                                    .map_err(|e| PgWireError::ApiError(Box::new(e)))
-                            }
-                            DescribeType::CONST(filelds) => {
-                                Ok(DescribePortalResponse::new(filelds.clone()))
-                            }
-                        }
-                    }
-                    ExecuteType::EXECUTE => {
-                        Ok(DescribePortalResponse::new(vec![]))
-                    }
-                }
-            },
-            None => {
-                Err(PgWireError::ApiError(Box::new(
-                        UnknownError::UnknownError(format!("ExtendedQueryHandler.do_describe_portal No matching query found: {}", query)),
-                    )))
-            },
+        // 已经开始执行的 portal 直接使用它的结果列
+        if let Some(header) = self.open_portal_header(portal) {
+            return Ok(DescribePortalResponse::new(header.as_ref().clone()));
+        }
+        let query = &portal.statement.statement.query;
+        let format = portal.result_column_format.clone();
+        // 结果列不依赖参数, 这里只检查参数能否解码
+        let fields = match get_params(portal) {
+            Ok(_) => self
+                .describe_fields(query, statement_name(&portal.statement), format)
+                .await
+                .map_err(|e| portal.statement.statement.map_error(e)),
+            Err(e) => {
+                self.fail_transaction();
+                Err(e)
+            }
+        };
+        client.set_transaction_status(self.transaction_status());
+        Ok(DescribePortalResponse::new(fields?))
+    }
+}
+
+#[async_trait]
+impl CopyHandler for FatherDuckQueryHandler {
+    async fn on_copy_data<C>(&self, _client: &mut C, copy_data: CopyData) -> PgWireResult<()>
+    where
+        C: ClientInfo + Sink<PgWireBackendMessage> + Unpin + Send + Sync,
+        C::Error: Debug,
+        PgWireError: From<<C as Sink<PgWireBackendMessage>>::Error>,
+    {
+        let sender = self.copy_in.lock().unwrap().as_ref().map(|copy_in| copy_in.sender.clone());
+        // 写入失败时工作线程已经结束了这次 COPY, 错误在 CopyDone 时报告
+        if let Some(sender) = sender {
+            let _ = sender.send(CopyMessage::Data(copy_data.data)).await;
+        }
+        Ok(())
+    }
+
+    async fn on_copy_done<C>(&self, client: &mut C, _done: CopyDone) -> PgWireResult<()>
+    where
+        C: ClientInfo + Sink<PgWireBackendMessage> + Unpin + Send + Sync,
+        C::Error: Debug,
+        PgWireError: From<<C as Sink<PgWireBackendMessage>>::Error>,
+    {
+        let copy_in = self.copy_in.lock().unwrap().take().ok_or_else(copy_aborted)?;
+        let _ = copy_in.sender.send(CopyMessage::Done).await;
+        let result = copy_in.result.await.map_err(|_| session_worker_gone()).and_then(|result| result);
+        if result.is_err() {
+            self.fail_transaction();
+        }
+        client.set_transaction_status(self.transaction_status());
+        let tag = Tag::new("COPY").with_rows(result?);
+        client.send(PgWireBackendMessage::CommandComplete(tag.into())).await?;
+        Ok(())
+    }
+
+    async fn on_copy_fail<C>(&self, client: &mut C, fail: CopyFail) -> PgWireError
+    where
+        C: ClientInfo + Sink<PgWireBackendMessage> + Unpin + Send + Sync,
+        C::Error: Debug,
+        PgWireError: From<<C as Sink<PgWireBackendMessage>>::Error>,
+    {
+        // 丢弃 sender 后工作线程回滚这次 COPY, 等它结束再返回
+        let copy_in = self.copy_in.lock().unwrap().take();
+        if let Some(copy_in) = copy_in {
+            drop(copy_in.sender);
+            let _ = copy_in.result.await;
+        }
+        self.fail_transaction();
+        client.set_transaction_status(self.transaction_status());
+        PgWireError::UserError(Box::new(ErrorInfo::new(
+            "ERROR".to_owned(),
+            "57014".to_owned(),
+            format!("COPY from stdin failed: {}", fail.message),
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::{Buf, Bytes};
+    use futures::StreamExt;
+
+    use super::*;
+    use pgwire::api::stmt::QueryParser;
+    use pgwire::messages::extendedquery::Bind;
+    use crate::parser::SourceMap;
+
+    fn handler() -> Arc<FatherDuckQueryHandler> {
+        let database = Connection::open_in_memory().unwrap();
+        FatherDuckQueryHandler::new(MyConnection::new(database).unwrap(), Tz::UTC)
+    }
+
+    async fn run(handler: &FatherDuckQueryHandler, query: &str) -> PgWireResult<PortalResult> {
+        handler.run_statement(query.to_owned(), None, vec![], Format::UnifiedText).await
+    }
+
+    async fn query_rows(handler: &FatherDuckQueryHandler, query: &str) -> PgWireResult<PortalCursor> {
+        handler
+            .query_rows(query.to_owned(), None, vec![], Format::UnifiedText, &classify_statement(query))
+            .await
+    }
+
+    // 第一行的列类型和各列按 format 编码后的值
+    async fn first_row(handler: &FatherDuckQueryHandler, query: &str, format: Format) -> (Vec<Type>, Vec<Option<Vec<u8>>>) {
+        let mut cursor = handler
+            .query_rows(query.to_owned(), None, vec![], format, &classify_statement(query))
+            .await
+            .unwrap();
+        let types = cursor.header.iter().map(|field| field.datatype().clone()).collect();
+        let row = cursor.next_row().await.unwrap().unwrap();
+        let mut data = row.data.freeze();
+        let values = (0..row.field_count)
+            .map(|_| match data.get_i32() {
+                -1 => None,
+                len => Some(data.split_to(len as usize).to_vec()),
+            })
+            .collect();
+        (types, values)
+    }
+
+    // 无效的查询通过表头 channel 返回错误, 工作线程不会 panic, 后面的查询照常执行
+    #[tokio::test]
+    async fn test_query_error_keeps_worker() {
+        let handler = handler();
+        match query_rows(&handler, "SELECT * FROM missing_table").await {
+            Err(PgWireError::UserError(error_info)) => assert_eq!(error_info.code, "42P01"),
+            Err(e) => panic!("unexpected error: {:?}", e),
+            Ok(_) => panic!("query should fail"),
         }
+        let cursor = query_rows(&handler, "SELECT 1").await.unwrap();
+        let rows: Vec<PgWireResult<DataRow>> = cursor.into_stream().collect().await;
+        assert_eq!(rows.len(), 1);
+    }
+
+    fn portal(query: &str, parameter_types: Vec<Type>, parameters: Vec<Option<Bytes>>) -> Portal<FatherDuckStatement> {
+        let statement = FatherDuckStatement {
+            query: query.to_owned(),
+            source: query.to_owned(),
+            source_map: SourceMap::default(),
+            parameter_types: parameter_types.clone(),
+        };
+        // Portal 是 non_exhaustive 的, 只能通过 Bind 构造
+        let bind = Bind::new(None, None, vec![FieldFormat::Binary.value()], parameters, vec![]);
+        let statement = Arc::new(StoredStatement::new(DEFAULT_NAME.to_owned(), statement, parameter_types));
+        Portal::try_new(&bind, statement).unwrap()
+    }
+
+    // 参数解码失败时事务同样进入失败状态, ReadyForQuery 报告 'E'
+    #[tokio::test]
+    async fn test_bad_parameter_fails_transaction() {
+        let handler = handler();
+        assert!(run(&handler, "BEGIN").await.is_ok());
+        let portal = portal("SELECT $1", vec![Type::INT4], vec![Some(Bytes::from_static(&[0, 1]))]);
+        assert!(handler.execute_portal(&portal).await.is_err());
+        assert_eq!(handler.transaction_status(), TransactionStatus::Error);
+    }
+
+    // Parse 不在 DuckDB 中执行任何东西: 多条语句的文本不会被执行, 事务也不受影响.
+    // 没有指定类型的参数以文本绑定, 由 DuckDB 转换成列的类型
+    #[tokio::test]
+    async fn test_parse_does_not_execute() {
+        let handler = handler();
+        assert!(run(&handler, "CREATE TABLE t (i INTEGER)").await.is_ok());
+        assert!(run(&handler, "BEGIN").await.is_ok());
+        let parser = handler.query_parser();
+        let statement = parser.parse_sql("INSERT INTO t VALUES ($1); DROP TABLE t", &[]).await.unwrap();
+        assert_eq!(statement.parameter_types, vec![Type::UNKNOWN]);
+        let statement = parser.parse_sql("SELECT * FROM t WHERE i = $1 OR i = $2", &[Type::INT4]).await.unwrap();
+        assert_eq!(statement.parameter_types, vec![Type::INT4, Type::UNKNOWN]);
+        assert!(run(&handler, "INSERT INTO t VALUES (1)").await.is_ok());
+        assert!(run(&handler, "COMMIT").await.is_ok());
+        assert_eq!(handler.transaction_status(), TransactionStatus::Idle);
+
+        let portal = portal("SELECT i FROM t WHERE i = $1", vec![Type::UNKNOWN], vec![Some(Bytes::from_static(b"1"))]);
+        let cursor = match handler.execute_portal(&portal).await {
+            Ok(PortalResult::Rows(cursor)) => cursor,
+            _ => panic!("query should return rows"),
+        };
+        let rows: Vec<PgWireResult<DataRow>> = cursor.into_stream().collect().await;
+        assert_eq!(rows.len(), 1);
+    }
+
+    // 两次部分 Execute 之间执行了其它语句, 挂起的 portal 仍然从上次停下的位置继续
+    #[tokio::test]
+    async fn test_suspended_portal_survives_other_statements() {
+        let handler = handler();
+        let portal = Arc::new(portal("SELECT * FROM range(5000)", vec![], vec![]));
+        let mut cursor = match handler.execute_portal(&portal).await {
+            Ok(PortalResult::Rows(cursor)) => cursor,
+            _ => panic!("query should return rows"),
+        };
+        for _ in 0..10 {
+            assert!(matches!(cursor.next_row().await, Some(Ok(_))));
+        }
+        handler.keep_portal_state(DEFAULT_NAME.to_owned(), portal.clone(), PortalState::Open(cursor));
+
+        // 结果集比 channel 大, 执行语句前不释放工作线程的话会一直等待
+        let other = match run(&handler, "SELECT 42").await {
+            Ok(PortalResult::Rows(cursor)) => cursor,
+            _ => panic!("query should return rows"),
+        };
+        assert_eq!(other.into_stream().collect::<Vec<_>>().await.len(), 1);
+
+        let mut cursor = match handler.take_portal_state(DEFAULT_NAME, &portal) {
+            Some(PortalState::Open(cursor)) => cursor,
+            _ => panic!("portal should still be open"),
+        };
+        let mut rows = 0;
+        while let Some(row) = cursor.next_row().await {
+            assert!(row.is_ok());
+            rows += 1;
+        }
+        assert_eq!(rows, 4990);
+    }
+
+    // 事务结束时挂起的 portal 被关闭, 不会把剩下的结果读到内存里; Describe 已经执行的 portal 不需要工作线程
+    #[tokio::test]
+    async fn test_transaction_end_closes_portals() {
+        let handler = handler();
+        assert!(run(&handler, "BEGIN").await.is_ok());
+        let portal = Arc::new(portal("SELECT * FROM range(5000)", vec![], vec![]));
+        let mut cursor = match handler.execute_portal(&portal).await {
+            Ok(PortalResult::Rows(cursor)) => cursor,
+            _ => panic!("query should return rows"),
+        };
+        assert!(matches!(cursor.next_row().await, Some(Ok(_))));
+        handler.keep_portal_state(DEFAULT_NAME.to_owned(), portal.clone(), PortalState::Open(cursor));
+        assert_eq!(handler.open_portal_header(&portal).map(|header| header.len()), Some(1));
+
+        assert!(run(&handler, "COMMIT").await.is_ok());
+        assert!(matches!(handler.take_portal_state(DEFAULT_NAME, &portal), Some(PortalState::Closed)));
+        assert!(handler.open_portal_header(&portal).is_none());
+    }
+
+    // 只有 tag 本身带行数的语句才追加行数
+    #[tokio::test]
+    async fn test_command_tag_rows() {
+        let handler = handler();
+        let mut tags = vec![];
+        for query in ["SELECT * FROM range(3)", "EXPLAIN SELECT 1", "SHOW TABLES"] {
+            let mut cursor = query_rows(&handler, query).await.unwrap();
+            while let Some(row) = cursor.next_row().await {
+                assert!(row.is_ok());
+            }
+            tags.push((cursor.command_tag, cursor.with_rows));
+        }
+        assert_eq!(tags, vec![
+            ("SELECT".to_owned(), true),
+            ("EXPLAIN".to_owned(), false),
+            ("SHOW".to_owned(), false),
+        ]);
+    }
+
+    // DDL 之后缓存的语句按新的表结构重新 PREPARE, 结果列不会停留在旧的表结构上
+    #[tokio::test]
+    async fn test_fields_follow_ddl() {
+        let handler = handler();
+        assert!(run(&handler, "CREATE TABLE t (i INTEGER)").await.is_ok());
+        let cursor = query_rows(&handler, "SELECT * FROM t").await.unwrap();
+        assert_eq!(cursor.header.len(), 1);
+        drop(cursor);
+        assert!(run(&handler, "ALTER TABLE t ADD COLUMN j VARCHAR").await.is_ok());
+        let cursor = query_rows(&handler, "SELECT * FROM t").await.unwrap();
+        assert_eq!(cursor.header.len(), 2);
+    }
+
+    // 所有返回结果集的语句都能 Describe, 而且 Describe 不会执行语句
+    #[tokio::test]
+    async fn test_describe_fields() {
+        let handler = handler();
+        assert!(run(&handler, "CREATE TABLE t (i INTEGER, s VARCHAR)").await.is_ok());
+        let describe = |query: &'static str| handler.describe_fields(query, None, Format::UnifiedText);
+
+        let fields = describe("INSERT INTO t VALUES (1, 'a') RETURNING i").await.unwrap();
+        assert_eq!(fields.iter().map(|field| field.datatype().clone()).collect::<Vec<_>>(), vec![Type::INT4]);
+        assert_eq!(describe("UPDATE t SET s = 'b' RETURNING s, i").await.unwrap().len(), 2);
+        assert_eq!(describe("EXPLAIN SELECT * FROM t").await.unwrap().len(), 2);
+        assert_eq!(describe("PRAGMA table_info('t')").await.unwrap().len(), 6);
+        assert!(!describe("CALL pragma_version()").await.unwrap().is_empty());
+        assert!(describe("INSERT INTO t VALUES (2, 'c')").await.unwrap().is_empty());
+
+        let mut cursor = query_rows(&handler, "SELECT * FROM t").await.unwrap();
+        assert!(cursor.next_row().await.is_none());
+    }
+
+    // ENUM 类型按结果列声明的类型确定, 和有没有数据无关. 其它会话创建的 ENUM 在执行下一条语句之前可见
+    #[tokio::test]
+    async fn test_enum_types_across_sessions() {
+        let database = Connection::open_in_memory().unwrap();
+        let session = || {
+            FatherDuckQueryHandler::new(MyConnection::new(database.try_clone().unwrap()).unwrap(), Tz::UTC)
+        };
+        let (first, second) = (session(), session());
+        assert!(run(&second, "SELECT 1").await.is_ok());
+        assert!(run(&first, "CREATE TYPE mood AS ENUM ('sad', 'happy')").await.is_ok());
+
+        let header = match run(&second, "SELECT 'sad'::mood AS m WHERE false").await {
+            Ok(PortalResult::Rows(cursor)) => cursor.header,
+            _ => panic!("query should return rows"),
+        };
+        assert_eq!(header[0].datatype().name(), "mood");
+        assert!(matches!(header[0].datatype().kind(), Kind::Enum(_)));
+        let fields = second.describe_fields("SELECT 'happy'::mood", None, Format::UnifiedText).await.unwrap();
+        assert_eq!(fields[0].datatype().name(), "mood");
+    }
+
+    // BIT 列和 BLOB 列的数据相同, 只能靠扩展类型区分
+    #[tokio::test]
+    async fn test_bit_column() {
+        let handler = handler();
+        assert!(run(&handler, "CREATE TABLE t (b BIT, v BLOB)").await.is_ok());
+        assert!(run(&handler, "INSERT INTO t VALUES ('10101', '\\x03\\xF5')").await.is_ok());
+        let (types, values) = first_row(&handler, "SELECT b, v FROM t", Format::UnifiedText).await;
+        assert_eq!(types, vec![Type::VARBIT, Type::BYTEA]);
+        assert_eq!(values, vec![Some(b"10101".to_vec()), Some(b"\\x03f5".to_vec())]);
+        let (_, values) = first_row(&handler, "SELECT b, [b] FROM t", Format::UnifiedBinary).await;
+        assert_eq!(values[0], Some(vec![0, 0, 0, 5, 0b1010_1000]));
+        let fields = handler.describe_fields("SELECT b, [b] FROM t", None, Format::UnifiedText).await.unwrap();
+        assert_eq!(fields.iter().map(|field| field.datatype().clone()).collect::<Vec<_>>(), vec![Type::VARBIT, Type::VARBIT_ARRAY]);
+    }
+
+    // TIMETZ 带着时区偏移以 timetz 返回, 数组同样如此
+    #[tokio::test]
+    async fn test_timetz() {
+        let handler = handler();
+        assert!(run(&handler, "CREATE TABLE t (a TIMETZ, b TIME)").await.is_ok());
+        assert!(run(&handler, "INSERT INTO t VALUES ('11:30:00-02:00', '11:30:00')").await.is_ok());
+        let (types, values) = first_row(&handler, "SELECT a, b, [a] FROM t", Format::UnifiedText).await;
+        assert_eq!(types, vec![Type::TIMETZ, Type::TIME, Type::TIMETZ_ARRAY]);
+        assert_eq!(values[0], Some(b"11:30:00-02".to_vec()));
+        assert_eq!(values[2], Some(b"{11:30:00-02}".to_vec()));
+        let (_, values) = first_row(&handler, "SELECT a FROM t", Format::UnifiedBinary).await;
+        assert_eq!(values[0], Some([&41_400_000_000i64.to_be_bytes()[..], &7200i32.to_be_bytes()].concat()));
+
+        let fields = handler.describe_fields("SELECT a, b FROM t", None, Format::UnifiedText).await.unwrap();
+        assert_eq!(fields.iter().map(|field| field.datatype().clone()).collect::<Vec<_>>(), vec![Type::TIMETZ, Type::TIME]);
     }
 }
@@ -1,30 +1,52 @@
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 
 use pgwire::api::auth::md5pass::Md5PasswordAuthStartupHandler;
-use pgwire::api::auth::DefaultServerParameterProvider;
-use pgwire::api::copy::NoopCopyHandler;
-use pgwire::api::PgWireServerHandlers;
+use pgwire::api::auth::{DefaultServerParameterProvider, ServerParameterProvider};
+use pgwire::api::{ClientInfo, PgWireServerHandlers};
+use pgwire::error::PgWireResult;
 use pgwire::tokio::process_socket;
 use tokio::net::TcpListener;
 use duckdb::Connection;
+use chrono_tz::Tz;
 
 use crate::auth::FatherDuckAuthSource;
 use crate::query::FatherDuckQueryHandler;
-use crate::connection::MyConnection;
-use crate::error::FatherDuckErrorHandler;
+use crate::connection::{current_time_zone, MyConnection};
+use crate::error::{into_pg_error, log_error, FatherDuckErrorHandler};
 use crate::config::{FATHERDUCK_CONFIG, MEMORY_PATH};
+use crate::types::IntervalStyle;
 
 struct DuckDBBackendFactory {
     query_handler: Arc<FatherDuckQueryHandler>,
     error_handler: Arc<FatherDuckErrorHandler>,
+    time_zone: Tz,
+}
+
+// 在 pgwire 默认参数的基础上报告会话的 TimeZone 和 IntervalStyle, client 据此解析 timestamptz 和 interval
+struct FatherDuckServerParameterProvider {
+    default: DefaultServerParameterProvider,
+    time_zone: Tz,
+}
+
+impl ServerParameterProvider for FatherDuckServerParameterProvider {
+    fn server_parameters<C>(&self, client: &C) -> Option<HashMap<String, String>>
+    where
+        C: ClientInfo,
+    {
+        let mut params = self.default.server_parameters(client).unwrap_or_default();
+        params.insert("TimeZone".to_owned(), self.time_zone.name().to_owned());
+        params.insert("IntervalStyle".to_owned(), IntervalStyle::Postgres.name().to_owned());
+        Some(params)
+    }
 }
 
 impl PgWireServerHandlers for DuckDBBackendFactory {
     type StartupHandler =
-        Md5PasswordAuthStartupHandler<FatherDuckAuthSource, DefaultServerParameterProvider>;
+        Md5PasswordAuthStartupHandler<FatherDuckAuthSource, FatherDuckServerParameterProvider>;
     type SimpleQueryHandler = FatherDuckQueryHandler;
     type ExtendedQueryHandler = FatherDuckQueryHandler;
-    type CopyHandler = NoopCopyHandler;
+    type CopyHandler = FatherDuckQueryHandler;
     type ErrorHandler = FatherDuckErrorHandler;
 
     fn simple_query_handler(&self) -> Arc<Self::SimpleQueryHandler> {
@@ -38,12 +60,15 @@ impl PgWireServerHandlers for DuckDBBackendFactory {
     fn startup_handler(&self) -> Arc<Self::StartupHandler> {
         Arc::new(Md5PasswordAuthStartupHandler::new(
             Arc::new(FatherDuckAuthSource),
-            Arc::new(DefaultServerParameterProvider::default()),
+            Arc::new(FatherDuckServerParameterProvider {
+                default: DefaultServerParameterProvider::default(),
+                time_zone: self.time_zone,
+            }),
         ))
     }
 
     fn copy_handler(&self) -> Arc<Self::CopyHandler> {
-        Arc::new(NoopCopyHandler)
+        self.query_handler.clone()
     }
 
     fn error_handler(&self) -> Arc<Self::ErrorHandler> {
@@ -51,28 +76,49 @@ impl PgWireServerHandlers for DuckDBBackendFactory {
     }
 }
 
-fn new_connection() -> MyConnection {
-    let conn;
+// 整个进程只打开一次数据库, 所有会话共享同一个 DuckDB 实例
+fn open_database() -> Connection {
     if FATHERDUCK_CONFIG.path == MEMORY_PATH {
-        conn = MyConnection::new(Connection::open_in_memory().unwrap());
+        Connection::open_in_memory().unwrap()
     } else {
-        conn = MyConnection::new(Connection::open(&FATHERDUCK_CONFIG.path).unwrap());
+        Connection::open(&FATHERDUCK_CONFIG.path).unwrap()
     }
-    conn
+}
+
+// 每个会话从共享实例克隆出自己的连接, 时区和 ENUM 类型在会话的工作线程上读取, 不阻塞 accept 循环.
+// 之后其它会话创建或删除 ENUM 时, 本会话在执行下一条语句之前刷新
+async fn new_session(database: &Mutex<Connection>) -> PgWireResult<DuckDBBackendFactory> {
+    let conn = database
+        .lock()
+        .unwrap()
+        .try_clone()
+        .map_err(|e| into_pg_error(e, ""))?;
+    let conn = MyConnection::new(conn)?;
+    let time_zone = conn.run(current_time_zone).await?;
+    let query_handler = FatherDuckQueryHandler::new(conn, time_zone);
+    query_handler.sync_pg_catalog().await?;
+    Ok(DuckDBBackendFactory {
+        query_handler,
+        error_handler: Arc::new(FatherDuckErrorHandler::new()),
+        time_zone,
+    })
 }
 
 pub async fn start_server() {
+    let database = Arc::new(Mutex::new(open_database()));
     let server_addr = format!("{}:{}", &FATHERDUCK_CONFIG.host, &FATHERDUCK_CONFIG.port);
     let listener = TcpListener::bind(&server_addr).await.unwrap();
     println!("Listening to {}", server_addr);
     loop {
         let incoming_socket = listener.accept().await.unwrap();
-
-        let factory = DuckDBBackendFactory {
-            query_handler: Arc::new(FatherDuckQueryHandler::new(new_connection())),
-            error_handler: Arc::new(FatherDuckErrorHandler::new()),
-        };
-
-        tokio::spawn(async move { process_socket(incoming_socket.0, None, factory).await });
+        let database = database.clone();
+        tokio::spawn(async move {
+            match new_session(&database).await {
+                Ok(factory) => {
+                    let _ = process_socket(incoming_socket.0, None, factory).await;
+                }
+                Err(e) => log_error("open session failed", &e),
+            }
+        });
     }
 }
@@ -0,0 +1,2152 @@
+use std::error::Error;
+use std::fmt::Write;
+
+use bytes::{Buf, BufMut, BytesMut};
+use chrono::{DateTime, Duration, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use chrono_tz::Tz;
+use duckdb::arrow::array::{Array, AsArray};
+use duckdb::arrow::datatypes::{
+    DataType, Date32Type, Decimal128Type, Field, Float32Type, Float64Type, Int16Type, Int32Type, Int64Type, Int8Type,
+    IntervalMonthDayNanoType, IntervalUnit, Time64MicrosecondType, Time64NanosecondType, TimeUnit as ArrowTimeUnit,
+    TimestampMicrosecondType, TimestampMillisecondType, TimestampNanosecondType, TimestampSecondType, UInt16Type,
+    UInt32Type, UInt64Type, UInt8Type,
+};
+use duckdb::types::{OrderedMap, TimeUnit, Value};
+use pgwire::api::results::FieldFormat;
+use pgwire::api::Type;
+use pgwire::error::PgWireResult;
+use pgwire::types::ToSqlText;
+use postgres_types::{to_sql_checked, FromSql, IsNull, Kind, ToSql};
+use rust_decimal::Decimal;
+
+use crate::config::{JsonFormat, NestedFormat, FATHERDUCK_CONFIG};
+use crate::error::{invalid_value_error, unsupported_error};
+
+pub fn into_pg_type(df_type: &DataType) -> PgWireResult<Type> {
+    Ok(match df_type {
+        DataType::Null => Type::UNKNOWN,
+        DataType::Boolean => Type::BOOL,
+        // PostgreSQL 没有单字节整数, "char" 是单个字符而不是数字, TINYINT 和 UTINYINT 一样按 int2 返回.
+        // 无符号整数映射到更宽的有符号类型, 避免溢出
+        DataType::Int8 | DataType::Int16 | DataType::UInt8 => Type::INT2,
+        DataType::Int32 | DataType::UInt16 => Type::INT4,
+        DataType::Int64 | DataType::UInt32 => Type::INT8,
+        DataType::UInt64 => Type::NUMERIC,
+        DataType::Timestamp(_, None) => Type::TIMESTAMP,
+        DataType::Timestamp(_, Some(_)) => Type::TIMESTAMPTZ,
+        DataType::Time32(_) | DataType::Time64(_) => Type::TIME,
+        DataType::Date32 | DataType::Date64 => Type::DATE,
+        DataType::Binary => Type::BYTEA,
+        DataType::Float32 => Type::FLOAT4,
+        DataType::Float64 => Type::FLOAT8,
+        DataType::Utf8 => Type::VARCHAR,
+        DataType::Interval(_) => Type::INTERVAL,
+        DataType::Decimal128(_, _) => Type::NUMERIC,
+        // ENUM 是字典类型, 查不到对应的 ENUM 类型时按 label 的类型返回
+        DataType::Dictionary(_, value_type) => into_pg_type(value_type)?,
+        // 嵌套的 LIST 对应 PostgreSQL 的多维数组, 类型和一维数组相同
+        DataType::List(field) | DataType::LargeList(field) | DataType::FixedSizeList(field, _) => {
+            into_pg_array_type(&into_pg_field_type(field)?)?
+        }
+        // STRUCT / UNION 按配置以 json, jsonb 或 record 返回, record 格式下 MAP 是 (key, value) 的数组
+        DataType::Struct(_) | DataType::Union(_, _) => nested_pg_type(),
+        DataType::Map(_, _) => match FATHERDUCK_CONFIG.nested_format {
+            NestedFormat::Record => Type::RECORD_ARRAY,
+            _ => nested_pg_type(),
+        },
+        _ => {
+            return Err(unsupported_error(format!("Unsupported Datatype {df_type}")));
+        }
+    })
+}
+
+pub fn into_pg_array_type(element_type: &Type) -> PgWireResult<Type> {
+    Ok(match element_type {
+        &Type::BOOL => Type::BOOL_ARRAY,
+        &Type::CHAR => Type::CHAR_ARRAY,
+        &Type::INT2 => Type::INT2_ARRAY,
+        &Type::INT4 => Type::INT4_ARRAY,
+        &Type::INT8 => Type::INT8_ARRAY,
+        &Type::NUMERIC => Type::NUMERIC_ARRAY,
+        &Type::FLOAT4 => Type::FLOAT4_ARRAY,
+        &Type::FLOAT8 => Type::FLOAT8_ARRAY,
+        &Type::VARCHAR => Type::VARCHAR_ARRAY,
+        &Type::BYTEA => Type::BYTEA_ARRAY,
+        &Type::DATE => Type::DATE_ARRAY,
+        &Type::TIME => Type::TIME_ARRAY,
+        &Type::TIMETZ => Type::TIMETZ_ARRAY,
+        &Type::TIMESTAMP => Type::TIMESTAMP_ARRAY,
+        &Type::TIMESTAMPTZ => Type::TIMESTAMPTZ_ARRAY,
+        &Type::INTERVAL => Type::INTERVAL_ARRAY,
+        &Type::UUID => Type::UUID_ARRAY,
+        &Type::BIT => Type::BIT_ARRAY,
+        &Type::VARBIT => Type::VARBIT_ARRAY,
+        &Type::JSON => Type::JSON_ARRAY,
+        &Type::JSONB => Type::JSONB_ARRAY,
+        &Type::RECORD => Type::RECORD_ARRAY,
+        // 只包含 NULL 的 LIST
+        &Type::UNKNOWN => Type::TEXT_ARRAY,
+        array_type if matches!(array_type.kind(), Kind::Array(_)) => array_type.clone(),
+        list_type => {
+            return Err(unsupported_error(format!("Unsupported List Datatype {list_type}")));
+        }
+    })
+}
+
+// 会话开启了 arrow_lossless_conversion, Arrow 中没有对应类型的值由 DuckDB 以扩展类型导出, 只能从字段的 metadata 中识别
+const ARROW_EXTENSION_NAME: &str = "ARROW:extension:name";
+// JSON 是带有 arrow.json 扩展类型的字符串
+const ARROW_JSON_EXTENSION: &str = "arrow.json";
+// BIT 在 Arrow 中是 BLOB, 带有 duckdb.bit 扩展类型时才能和 BLOB 区分
+const ARROW_BIT_EXTENSION: &str = "duckdb.bit";
+// UUID 是 16 字节的定长二进制, 字节顺序和 PostgreSQL 的 uuid 相同
+const ARROW_UUID_EXTENSION: &str = "arrow.uuid";
+// TIMETZ 是 8 字节的 dtime_tz_t, 不开启时被导出成 TIME, 时区偏移会丢失
+const ARROW_TIME_TZ_EXTENSION: &str = "duckdb.time_tz";
+// HUGEINT, UHUGEINT 是 16 字节的小端整数
+const ARROW_HUGEINT_EXTENSION: &str = "duckdb.hugeint";
+const ARROW_UHUGEINT_EXTENSION: &str = "duckdb.uhugeint";
+
+fn extension_name(field: &Field) -> Option<&str> {
+    field.metadata().get(ARROW_EXTENSION_NAME).map(String::as_str)
+}
+
+// 和 into_pg_type 相同, 但是会先按字段的扩展类型确定 PostgreSQL 类型
+pub fn into_pg_field_type(field: &Field) -> PgWireResult<Type> {
+    Ok(match extension_name(field) {
+        Some(ARROW_JSON_EXTENSION) => json_pg_type(),
+        Some(ARROW_BIT_EXTENSION) => Type::VARBIT,
+        Some(ARROW_UUID_EXTENSION) => Type::UUID,
+        Some(ARROW_TIME_TZ_EXTENSION) => Type::TIMETZ,
+        Some(ARROW_HUGEINT_EXTENSION | ARROW_UHUGEINT_EXTENSION) => Type::NUMERIC,
+        _ => into_pg_type(field.data_type())?,
+    })
+}
+
+fn json_pg_type() -> Type {
+    match FATHERDUCK_CONFIG.json_format {
+        JsonFormat::Json => Type::JSON,
+        JsonFormat::Jsonb => Type::JSONB,
+    }
+}
+
+fn nested_pg_type() -> Type {
+    match FATHERDUCK_CONFIG.nested_format {
+        NestedFormat::Json => Type::JSON,
+        NestedFormat::Jsonb => Type::JSONB,
+        NestedFormat::Record => Type::RECORD,
+    }
+}
+
+// 位串, 和 PostgreSQL 的 varbit 一样按字节从高位开始存放, 最后一个字节的低位补 0
+#[derive(Debug, Clone, PartialEq)]
+pub struct BitString {
+    len: usize,
+    bytes: Vec<u8>,
+}
+
+impl BitString {
+    // DuckDB 的 BIT 第一个字节是填充的位数, 填充位放在第二个字节的高位
+    pub fn from_duckdb(raw: &[u8]) -> Option<BitString> {
+        let (&padding, data) = raw.split_first()?;
+        let padding = padding as usize;
+        let len = (data.len() * 8).checked_sub(padding)?;
+        let bits = (0..len).map(|i| {
+            let pos = i + padding;
+            data[pos / 8] >> (7 - pos % 8) & 1 == 1
+        });
+        Some(BitString::from_bits(bits))
+    }
+
+    pub fn parse(text: &str) -> Option<BitString> {
+        let bits = text
+            .chars()
+            .map(|c| match c {
+                '0' => Some(false),
+                '1' => Some(true),
+                _ => None,
+            })
+            .collect::<Option<Vec<bool>>>()?;
+        Some(BitString::from_bits(bits))
+    }
+
+    fn from_bits(bits: impl IntoIterator<Item = bool>) -> BitString {
+        let mut bit_string = BitString { len: 0, bytes: vec![] };
+        for bit in bits {
+            if bit_string.len.is_multiple_of(8) {
+                bit_string.bytes.push(0);
+            }
+            if bit {
+                *bit_string.bytes.last_mut().unwrap() |= 0x80 >> (bit_string.len % 8);
+            }
+            bit_string.len += 1;
+        }
+        bit_string
+    }
+
+    fn bit(&self, i: usize) -> bool {
+        self.bytes[i / 8] & (0x80 >> (i % 8)) != 0
+    }
+
+    pub fn text(&self) -> String {
+        (0..self.len).map(|i| if self.bit(i) { '1' } else { '0' }).collect()
+    }
+}
+
+impl ToSql for BitString {
+    fn to_sql(&self, _ty: &Type, out: &mut BytesMut) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        out.put_i32(self.len as i32);
+        out.put_slice(&self.bytes);
+        Ok(IsNull::No)
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        matches!(*ty, Type::BIT | Type::VARBIT)
+    }
+
+    to_sql_checked!();
+}
+
+impl ToSqlText for BitString {
+    fn to_sql_text(&self, _ty: &Type, out: &mut BytesMut) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        out.put_slice(self.text().as_bytes());
+        Ok(IsNull::No)
+    }
+}
+
+impl<'a> FromSql<'a> for BitString {
+    fn from_sql(_ty: &Type, mut raw: &'a [u8]) -> Result<BitString, Box<dyn Error + Sync + Send>> {
+        if raw.len() < 4 {
+            return Err("invalid bit string length".into());
+        }
+        let len = raw.get_i32();
+        if len < 0 || raw.len() != (len as usize).div_ceil(8) {
+            return Err("invalid bit string length".into());
+        }
+        let bytes = raw.to_vec();
+        let bit_string = BitString { len: len as usize, bytes };
+        Ok(BitString::from_bits((0..bit_string.len).map(|i| bit_string.bit(i))))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        matches!(*ty, Type::BIT | Type::VARBIT)
+    }
+}
+
+// DuckDB 的 HUGEINT / UHUGEINT / UBIGINT 超出了 INT8 的范围, 以 NUMERIC 发送给 client.
+// UHUGEINT 超出了 i128 的范围, 分开保存符号和绝对值
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NumericInt {
+    negative: bool,
+    magnitude: u128,
+}
+
+impl From<i128> for NumericInt {
+    fn from(value: i128) -> NumericInt {
+        NumericInt { negative: value < 0, magnitude: value.unsigned_abs() }
+    }
+}
+
+impl From<u128> for NumericInt {
+    fn from(value: u128) -> NumericInt {
+        NumericInt { negative: false, magnitude: value }
+    }
+}
+
+impl ToSql for NumericInt {
+    fn to_sql(&self, _ty: &Type, out: &mut BytesMut) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        // 二进制格式: ndigits, weight, sign, dscale, 之后是以 10000 为基数的各位, 高位在前
+        let mut magnitude = self.magnitude;
+        let mut digits = Vec::new();
+        while magnitude > 0 {
+            digits.push((magnitude % 10000) as i16);
+            magnitude /= 10000;
+        }
+        let weight = digits.len().saturating_sub(1) as i16;
+        digits.reverse();
+        // 末尾的 0 由 weight 表示, 不需要发送
+        while digits.last() == Some(&0) {
+            digits.pop();
+        }
+
+        out.put_i16(digits.len() as i16);
+        out.put_i16(weight);
+        out.put_u16(if self.negative { 0x4000 } else { 0x0000 });
+        out.put_u16(0);
+        for digit in digits {
+            out.put_i16(digit);
+        }
+        Ok(IsNull::No)
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        *ty == Type::NUMERIC
+    }
+
+    to_sql_checked!();
+}
+
+impl ToSqlText for NumericInt {
+    fn to_sql_text(&self, _ty: &Type, out: &mut BytesMut) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        if self.negative {
+            out.put_u8(b'-');
+        }
+        out.put_slice(self.magnitude.to_string().as_bytes());
+        Ok(IsNull::No)
+    }
+}
+
+// PostgreSQL 的 IntervalStyle 会话变量, 决定 interval 的文本格式
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IntervalStyle {
+    Postgres,
+    PostgresVerbose,
+    SqlStandard,
+    Iso8601,
+}
+
+impl IntervalStyle {
+    pub fn parse(value: &str) -> Option<IntervalStyle> {
+        match value.to_lowercase().as_str() {
+            "postgres" => Some(IntervalStyle::Postgres),
+            "postgres_verbose" => Some(IntervalStyle::PostgresVerbose),
+            "sql_standard" => Some(IntervalStyle::SqlStandard),
+            "iso_8601" => Some(IntervalStyle::Iso8601),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            IntervalStyle::Postgres => "postgres",
+            IntervalStyle::PostgresVerbose => "postgres_verbose",
+            IntervalStyle::SqlStandard => "sql_standard",
+            IntervalStyle::Iso8601 => "iso_8601",
+        }
+    }
+}
+
+// DuckDB 的 interval 由 months / days / micros 三部分组成, 和 PostgreSQL 的存储方式相同
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PgInterval {
+    pub months: i32,
+    pub days: i32,
+    pub microseconds: i64,
+    pub style: IntervalStyle,
+}
+
+impl PgInterval {
+    pub fn new(months: i32, days: i32, microseconds: i64) -> PgInterval {
+        PgInterval { months, days, microseconds, style: IntervalStyle::Postgres }
+    }
+
+    pub fn with_style(self, style: IntervalStyle) -> PgInterval {
+        PgInterval { style, ..self }
+    }
+
+    // 和 PostgreSQL 的 EncodeInterval 一致: 各字段按截断除法拆分, 符号与原值相同
+    fn encode_text(&self) -> String {
+        let year = self.months / 12;
+        let mon = self.months % 12;
+        let mday = self.days as i64;
+        let hour = self.microseconds / 3_600_000_000;
+        let min = self.microseconds / 60_000_000 % 60;
+        let sec = self.microseconds / 1_000_000 % 60;
+        let fsec = self.microseconds % 1_000_000;
+        let year = year as i64;
+        let mon = mon as i64;
+
+        let mut out = String::new();
+        match self.style {
+            IntervalStyle::Postgres => {
+                let mut is_zero = true;
+                let mut is_before = false;
+                for (value, unit) in [(year, "year"), (mon, "mon"), (mday, "day")] {
+                    if value == 0 {
+                        continue;
+                    }
+                    let _ = write!(
+                        out,
+                        "{}{}{} {}{}",
+                        if is_zero { "" } else { " " },
+                        if is_before && value > 0 { "+" } else { "" },
+                        value,
+                        unit,
+                        if value != 1 { "s" } else { "" },
+                    );
+                    is_before = value < 0;
+                    is_zero = false;
+                }
+                if is_zero || hour != 0 || min != 0 || sec != 0 || fsec != 0 {
+                    let minus = hour < 0 || min < 0 || sec < 0 || fsec < 0;
+                    let _ = write!(
+                        out,
+                        "{}{}{:02}:{:02}:",
+                        if is_zero { "" } else { " " },
+                        if minus { "-" } else if is_before { "+" } else { "" },
+                        hour.abs(),
+                        min.abs(),
+                    );
+                    append_seconds(&mut out, sec, fsec, true);
+                }
+            }
+            IntervalStyle::PostgresVerbose => {
+                let mut is_zero = true;
+                let mut is_before = false;
+                out.push('@');
+                for (value, unit) in [(year, "year"), (mon, "mon"), (mday, "day"), (hour, "hour"), (min, "min")] {
+                    if value == 0 {
+                        continue;
+                    }
+                    let value = if is_zero {
+                        is_before = value < 0;
+                        value.abs()
+                    } else if is_before {
+                        -value
+                    } else {
+                        value
+                    };
+                    let _ = write!(out, " {} {}{}", value, unit, if value == 1 { "" } else { "s" });
+                    is_zero = false;
+                }
+                if sec != 0 || fsec != 0 {
+                    out.push(' ');
+                    if sec < 0 || (sec == 0 && fsec < 0) {
+                        if is_zero {
+                            is_before = true;
+                        } else if !is_before {
+                            out.push('-');
+                        }
+                    } else if is_before {
+                        out.push('-');
+                    }
+                    append_seconds(&mut out, sec, fsec, false);
+                    let _ = write!(out, " sec{}", if sec.abs() != 1 || fsec != 0 { "s" } else { "" });
+                    is_zero = false;
+                }
+                if is_zero {
+                    out.push_str(" 0");
+                }
+                if is_before {
+                    out.push_str(" ago");
+                }
+            }
+            IntervalStyle::SqlStandard => {
+                let fields = [year, mon, mday, hour, min, sec, fsec];
+                let has_negative = fields.iter().any(|v| *v < 0);
+                let has_positive = fields.iter().any(|v| *v > 0);
+                let has_year_month = year != 0 || mon != 0;
+                let has_day_time = mday != 0 || hour != 0 || min != 0 || sec != 0 || fsec != 0;
+                let sql_standard_value = !(has_negative && has_positive || has_year_month && has_day_time);
+                if !has_negative && !has_positive {
+                    out.push('0');
+                } else if !sql_standard_value {
+                    let year_sign = if year < 0 || mon < 0 { '-' } else { '+' };
+                    let day_sign = if mday < 0 { '-' } else { '+' };
+                    let sec_sign = if hour < 0 || min < 0 || sec < 0 || fsec < 0 { '-' } else { '+' };
+                    let _ = write!(
+                        out,
+                        "{}{}-{} {}{} {}{}:{:02}:",
+                        year_sign, year.abs(), mon.abs(), day_sign, mday.abs(), sec_sign, hour.abs(), min.abs(),
+                    );
+                    append_seconds(&mut out, sec, fsec, true);
+                } else {
+                    // 所有字段同号, 符号只在最前面出现一次
+                    if has_negative {
+                        out.push('-');
+                    }
+                    if has_year_month {
+                        let _ = write!(out, "{}-{}", year.abs(), mon.abs());
+                    } else if mday != 0 {
+                        let _ = write!(out, "{} {}:{:02}:", mday.abs(), hour.abs(), min.abs());
+                        append_seconds(&mut out, sec, fsec, true);
+                    } else {
+                        let _ = write!(out, "{}:{:02}:", hour.abs(), min.abs());
+                        append_seconds(&mut out, sec, fsec, true);
+                    }
+                }
+            }
+            IntervalStyle::Iso8601 => {
+                if year == 0 && mon == 0 && mday == 0 && self.microseconds == 0 {
+                    return "PT0S".to_owned();
+                }
+                out.push('P');
+                for (value, unit) in [(year, 'Y'), (mon, 'M'), (mday, 'D')] {
+                    if value != 0 {
+                        let _ = write!(out, "{}{}", value, unit);
+                    }
+                }
+                if self.microseconds != 0 {
+                    out.push('T');
+                }
+                for (value, unit) in [(hour, 'H'), (min, 'M')] {
+                    if value != 0 {
+                        let _ = write!(out, "{}{}", value, unit);
+                    }
+                }
+                if sec != 0 || fsec != 0 {
+                    if sec < 0 || fsec < 0 {
+                        out.push('-');
+                    }
+                    append_seconds(&mut out, sec, fsec, false);
+                    out.push('S');
+                }
+            }
+        }
+        out
+    }
+}
+
+// 秒数取绝对值输出, 小数部分去掉末尾的 0
+fn append_seconds(out: &mut String, sec: i64, fsec: i64, fill_zeros: bool) {
+    if fill_zeros {
+        let _ = write!(out, "{:02}", sec.abs());
+    } else {
+        let _ = write!(out, "{}", sec.abs());
+    }
+    if fsec != 0 {
+        let fraction = format!("{:06}", fsec.abs());
+        let _ = write!(out, ".{}", fraction.trim_end_matches('0'));
+    }
+}
+
+impl From<PgInterval> for Value {
+    fn from(interval: PgInterval) -> Value {
+        Value::Interval {
+            months: interval.months,
+            days: interval.days,
+            nanos: interval.microseconds * 1_000,
+        }
+    }
+}
+
+impl ToSql for PgInterval {
+    fn to_sql(&self, _ty: &Type, out: &mut BytesMut) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        out.put_i64(self.microseconds);
+        out.put_i32(self.days);
+        out.put_i32(self.months);
+        Ok(IsNull::No)
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        *ty == Type::INTERVAL
+    }
+
+    to_sql_checked!();
+}
+
+impl ToSqlText for PgInterval {
+    fn to_sql_text(&self, _ty: &Type, out: &mut BytesMut) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        out.put_slice(self.encode_text().as_bytes());
+        Ok(IsNull::No)
+    }
+}
+
+impl<'a> FromSql<'a> for PgInterval {
+    fn from_sql(_ty: &Type, mut raw: &'a [u8]) -> Result<Self, Box<dyn Error + Sync + Send>> {
+        if raw.len() != 16 {
+            return Err("invalid interval binary length".into());
+        }
+        let microseconds = raw.get_i64();
+        let days = raw.get_i32();
+        let months = raw.get_i32();
+        Ok(PgInterval::new(months, days, microseconds))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        *ty == Type::INTERVAL
+    }
+}
+
+// TIMETZ, offset 是 UTC 以东的秒数. PostgreSQL 的二进制格式是微秒数加上 UTC 以西的秒数
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PgTimeTz {
+    microseconds: i64,
+    offset: i32,
+}
+
+// DuckDB 的 dtime_tz_t: 高 40 位是微秒数, 低 24 位是 MAX_OFFSET 减去偏移量, 使相同时刻按偏移量排序
+const TIME_TZ_OFFSET_BITS: u32 = 24;
+const TIME_TZ_MAX_OFFSET: i32 = 16 * 60 * 60 - 1;
+
+impl PgTimeTz {
+    pub fn from_duckdb(bits: u64) -> PgTimeTz {
+        PgTimeTz {
+            microseconds: (bits >> TIME_TZ_OFFSET_BITS) as i64,
+            offset: TIME_TZ_MAX_OFFSET - (bits & ((1 << TIME_TZ_OFFSET_BITS) - 1)) as i32,
+        }
+    }
+
+    // 和 PostgreSQL 一样, 小数部分去掉末尾的 0, 偏移量为 0 的分和秒不输出
+    fn encode_text(&self) -> String {
+        let seconds = self.microseconds / 1_000_000;
+        let fraction = self.microseconds % 1_000_000;
+        let mut out = format!("{:02}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60);
+        if fraction != 0 {
+            out.push_str(format!(".{:06}", fraction).trim_end_matches('0'));
+        }
+        let offset = self.offset.unsigned_abs();
+        let _ = write!(out, "{}{:02}", if self.offset < 0 { '-' } else { '+' }, offset / 3600);
+        if !offset.is_multiple_of(3600) {
+            let _ = write!(out, ":{:02}", offset / 60 % 60);
+        }
+        if !offset.is_multiple_of(60) {
+            let _ = write!(out, ":{:02}", offset % 60);
+        }
+        out
+    }
+}
+
+impl ToSql for PgTimeTz {
+    fn to_sql(&self, _ty: &Type, out: &mut BytesMut) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        out.put_i64(self.microseconds);
+        out.put_i32(-self.offset);
+        Ok(IsNull::No)
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        *ty == Type::TIMETZ
+    }
+
+    to_sql_checked!();
+}
+
+impl ToSqlText for PgTimeTz {
+    fn to_sql_text(&self, _ty: &Type, out: &mut BytesMut) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        out.put_slice(self.encode_text().as_bytes());
+        Ok(IsNull::No)
+    }
+}
+
+impl<'a> FromSql<'a> for PgTimeTz {
+    fn from_sql(_ty: &Type, mut raw: &'a [u8]) -> Result<Self, Box<dyn Error + Sync + Send>> {
+        if raw.len() != 12 {
+            return Err("invalid timetz binary length".into());
+        }
+        let microseconds = raw.get_i64();
+        let offset = -raw.get_i32();
+        Ok(PgTimeTz { microseconds, offset })
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        *ty == Type::TIMETZ
+    }
+}
+
+// 编码结果时用到的会话变量
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SessionSettings {
+    // timestamptz 按会话时区输出
+    pub time_zone: Tz,
+    pub interval_style: IntervalStyle,
+}
+
+const BASE_DATE: NaiveDate = NaiveDate::from_ymd_opt(1970, 1, 1).unwrap();
+
+// PostgreSQL 的时间类型只有微秒精度, TIMESTAMP_NS 等纳秒值向下截断到微秒,
+// 和 DuckDB 把 TIMESTAMP_NS 转换成 TIMESTAMP 的结果一致. 秒和毫秒换算溢出时返回 None
+fn to_microseconds(unit: TimeUnit, value: i64) -> Option<i64> {
+    match unit {
+        TimeUnit::Second => value.checked_mul(1_000_000),
+        TimeUnit::Millisecond => value.checked_mul(1_000),
+        TimeUnit::Microsecond => Some(value),
+        TimeUnit::Nanosecond => Some(value.div_euclid(1_000)),
+    }
+}
+
+fn into_time_unit(unit: &ArrowTimeUnit) -> TimeUnit {
+    match unit {
+        ArrowTimeUnit::Second => TimeUnit::Second,
+        ArrowTimeUnit::Millisecond => TimeUnit::Millisecond,
+        ArrowTimeUnit::Microsecond => TimeUnit::Microsecond,
+        ArrowTimeUnit::Nanosecond => TimeUnit::Nanosecond,
+    }
+}
+
+// 取出 Arrow 数组中一行的值, 和 duckdb-rs 读取查询结果时的转换一致.
+// scale 为 0 的 DECIMAL 按整数取出, DECIMAL(38, 0) 超出了 rust_decimal 的范围
+pub fn array_value(array: &dyn Array, row: usize) -> PgWireResult<Value> {
+    if array.is_null(row) {
+        return Ok(Value::Null);
+    }
+    Ok(match array.data_type() {
+        DataType::Null => Value::Null,
+        DataType::Boolean => Value::Boolean(array.as_boolean().value(row)),
+        DataType::Int8 => Value::TinyInt(array.as_primitive::<Int8Type>().value(row)),
+        DataType::Int16 => Value::SmallInt(array.as_primitive::<Int16Type>().value(row)),
+        DataType::Int32 => Value::Int(array.as_primitive::<Int32Type>().value(row)),
+        DataType::Int64 => Value::BigInt(array.as_primitive::<Int64Type>().value(row)),
+        DataType::UInt8 => Value::UTinyInt(array.as_primitive::<UInt8Type>().value(row)),
+        DataType::UInt16 => Value::USmallInt(array.as_primitive::<UInt16Type>().value(row)),
+        DataType::UInt32 => Value::UInt(array.as_primitive::<UInt32Type>().value(row)),
+        DataType::UInt64 => Value::UBigInt(array.as_primitive::<UInt64Type>().value(row)),
+        DataType::Float32 => Value::Float(array.as_primitive::<Float32Type>().value(row)),
+        DataType::Float64 => Value::Double(array.as_primitive::<Float64Type>().value(row)),
+        DataType::Decimal128(_, 0) => Value::HugeInt(array.as_primitive::<Decimal128Type>().value(row)),
+        DataType::Decimal128(_, scale) => {
+            let value = array.as_primitive::<Decimal128Type>().value(row);
+            let decimal = Decimal::try_from_i128_with_scale(value, *scale as u32)
+                .map_err(|e| invalid_value_error(format!("decimal value {} out of range: {}", value, e)))?;
+            Value::Decimal(decimal)
+        }
+        DataType::Utf8 => Value::Text(array.as_string::<i32>().value(row).to_owned()),
+        DataType::LargeUtf8 => Value::Text(array.as_string::<i64>().value(row).to_owned()),
+        DataType::Binary => Value::Blob(array.as_binary::<i32>().value(row).to_vec()),
+        DataType::LargeBinary => Value::Blob(array.as_binary::<i64>().value(row).to_vec()),
+        // UUID, HUGEINT 等扩展类型, 由 PgValue::from_field_value 按字段解码
+        DataType::FixedSizeBinary(_) => Value::Blob(array.as_fixed_size_binary().value(row).to_vec()),
+        DataType::Date32 => Value::Date32(array.as_primitive::<Date32Type>().value(row)),
+        DataType::Time64(unit) => {
+            let value = match unit {
+                ArrowTimeUnit::Nanosecond => array.as_primitive::<Time64NanosecondType>().value(row),
+                _ => array.as_primitive::<Time64MicrosecondType>().value(row),
+            };
+            Value::Time64(into_time_unit(unit), value)
+        }
+        DataType::Timestamp(unit, _) => {
+            let value = match unit {
+                ArrowTimeUnit::Second => array.as_primitive::<TimestampSecondType>().value(row),
+                ArrowTimeUnit::Millisecond => array.as_primitive::<TimestampMillisecondType>().value(row),
+                ArrowTimeUnit::Microsecond => array.as_primitive::<TimestampMicrosecondType>().value(row),
+                ArrowTimeUnit::Nanosecond => array.as_primitive::<TimestampNanosecondType>().value(row),
+            };
+            Value::Timestamp(into_time_unit(unit), value)
+        }
+        DataType::Interval(IntervalUnit::MonthDayNano) => {
+            let (months, days, nanos) =
+                IntervalMonthDayNanoType::to_parts(array.as_primitive::<IntervalMonthDayNanoType>().value(row));
+            Value::Interval { months, days, nanos }
+        }
+        // ENUM 的字典里是全部 label, 按下标取出当前行的 label
+        DataType::Dictionary(key_type, _) => {
+            let key = match key_type.as_ref() {
+                DataType::UInt8 => array.as_dictionary::<UInt8Type>().keys().value(row) as usize,
+                DataType::UInt16 => array.as_dictionary::<UInt16Type>().keys().value(row) as usize,
+                DataType::UInt32 => array.as_dictionary::<UInt32Type>().keys().value(row) as usize,
+                other => return Err(unsupported_error(format!("Unsupported dictionary key {other}"))),
+            };
+            match array_value(array.as_any_dictionary().values().as_ref(), key)? {
+                Value::Text(label) => Value::Enum(label),
+                other => other,
+            }
+        }
+        DataType::List(_) => Value::List(array_values(array.as_list::<i32>().value(row).as_ref())?),
+        DataType::LargeList(_) => Value::List(array_values(array.as_list::<i64>().value(row).as_ref())?),
+        DataType::FixedSizeList(_, _) => Value::Array(array_values(array.as_fixed_size_list().value(row).as_ref())?),
+        DataType::Struct(fields) => {
+            let columns = array.as_struct().columns();
+            let items = fields
+                .iter()
+                .zip(columns)
+                .map(|(field, column)| Ok((field.name().clone(), array_value(column.as_ref(), row)?)))
+                .collect::<PgWireResult<Vec<(String, Value)>>>()?;
+            Value::Struct(OrderedMap::from(items))
+        }
+        DataType::Map(_, _) => {
+            let entries = array.as_map().value(row);
+            let entries = (0..entries.len())
+                .map(|idx| Ok((array_value(entries.column(0).as_ref(), idx)?, array_value(entries.column(1).as_ref(), idx)?)))
+                .collect::<PgWireResult<Vec<(Value, Value)>>>()?;
+            Value::Map(OrderedMap::from(entries))
+        }
+        // 稀疏和稠密 UNION 都只取出当前成员的值
+        DataType::Union(_, _) => Value::Union(Box::new(array_value(array.as_union().value(row).as_ref(), 0)?)),
+        other => return Err(unsupported_error(format!("Unsupported Datatype {other}"))),
+    })
+}
+
+fn array_values(array: &dyn Array) -> PgWireResult<Vec<Value>> {
+    (0..array.len()).map(|idx| array_value(array, idx)).collect()
+}
+
+// 一个 DuckDB 的值转换成 PostgreSQL 类型后的结果, 同时支持文本和二进制两种编码
+#[derive(Debug, Clone, PartialEq)]
+pub enum PgValue {
+    Null,
+    Bool(bool),
+    Int2(i16),
+    Int4(i32),
+    Int8(i64),
+    Float4(f32),
+    Float8(f64),
+    Numeric(Decimal),
+    NumericInt(NumericInt),
+    Text(String),
+    Bytea(Vec<u8>),
+    Date(NaiveDate),
+    Time(NaiveTime),
+    TimeTz(PgTimeTz),
+    Timestamp(NaiveDateTime),
+    Timestamptz(DateTime<FixedOffset>),
+    Interval(PgInterval),
+    Bit(BitString),
+    Array(PgArray),
+    Json(String),
+    Jsonb(String),
+    Uuid([u8; 16]),
+    // 每个字段带上自己的类型, 二进制格式需要字段的 oid
+    Record(Vec<(Type, PgValue)>),
+}
+
+impl PgValue {
+    // data_type 是值在 DuckDB 中的 Arrow 类型, 用来区分 timestamp 和 timestamptz, 以及确定嵌套类型里的字段类型.
+    // 无法确定时(例如 UNION 的成员)传入 DataType::Null, 按值本身转换
+    pub fn from_field_value(value: Value, field: &Field, settings: &SessionSettings) -> PgWireResult<PgValue> {
+        Ok(match (value, extension_name(field)) {
+            (Value::Text(text), Some(ARROW_JSON_EXTENSION)) => match FATHERDUCK_CONFIG.json_format {
+                JsonFormat::Json => PgValue::Json(text),
+                JsonFormat::Jsonb => PgValue::Jsonb(text),
+            },
+            (Value::Blob(raw), Some(ARROW_BIT_EXTENSION)) => BitString::from_duckdb(&raw)
+                .map(PgValue::Bit)
+                .ok_or_else(|| invalid_value_error("invalid bit string".to_owned()))?,
+            (Value::Blob(raw), Some(ARROW_UUID_EXTENSION)) => PgValue::Uuid(fixed_bytes(&raw, "uuid")?),
+            (Value::Blob(raw), Some(ARROW_TIME_TZ_EXTENSION)) => {
+                PgValue::TimeTz(PgTimeTz::from_duckdb(u64::from_le_bytes(fixed_bytes(&raw, "timetz")?)))
+            }
+            (Value::Blob(raw), Some(ARROW_HUGEINT_EXTENSION)) => {
+                PgValue::NumericInt(i128::from_le_bytes(fixed_bytes(&raw, "hugeint")?).into())
+            }
+            (Value::Blob(raw), Some(ARROW_UHUGEINT_EXTENSION)) => {
+                PgValue::NumericInt(u128::from_le_bytes(fixed_bytes(&raw, "uhugeint")?).into())
+            }
+            (value, _) => PgValue::from_value(value, field.data_type(), settings)?,
+        })
+    }
+
+    pub fn from_value(value: Value, data_type: &DataType, settings: &SessionSettings) -> PgWireResult<PgValue> {
+        Ok(match value {
+            Value::Null => PgValue::Null,
+            Value::Boolean(b) => PgValue::Bool(b),
+            Value::TinyInt(i) => PgValue::Int2(i as i16),
+            Value::SmallInt(i) => PgValue::Int2(i),
+            Value::Int(i) => PgValue::Int4(i),
+            Value::BigInt(i) => PgValue::Int8(i),
+            // 无符号整数使用更宽的有符号类型, 避免溢出
+            Value::UTinyInt(i) => PgValue::Int2(i as i16),
+            Value::USmallInt(i) => PgValue::Int4(i as i32),
+            Value::UInt(i) => PgValue::Int8(i as i64),
+            Value::UBigInt(i) => PgValue::NumericInt((i as u128).into()),
+            // scale 为 0 的 DECIMAL, HUGEINT 和 UHUGEINT 本身以扩展类型导出
+            Value::HugeInt(i) => PgValue::NumericInt(i.into()),
+            Value::Float(f) => PgValue::Float4(f),
+            Value::Double(f) => PgValue::Float8(f),
+            Value::Decimal(d) => PgValue::Numeric(d),
+            Value::Text(t) => PgValue::Text(t),
+            // ENUM 的文本和二进制格式都是 label 本身
+            Value::Enum(label) => PgValue::Text(label),
+            Value::Blob(b) => PgValue::Bytea(b),
+            Value::Date32(d) => {
+                let date = BASE_DATE
+                    .checked_add_signed(Duration::days(d as i64))
+                    .ok_or_else(|| invalid_value_error(format!("date value {} out of range", d)))?;
+                PgValue::Date(date)
+            }
+            Value::Time64(unit, v) => {
+                let micros = to_microseconds(unit, v)
+                    .ok_or_else(|| invalid_value_error(format!("time value {} out of range", v)))?;
+                let seconds = micros.div_euclid(1_000_000);
+                let microseconds = micros.rem_euclid(1_000_000) as u32;
+                let time = NaiveTime::from_num_seconds_from_midnight_opt(seconds as u32, microseconds * 1_000)
+                    .ok_or_else(|| invalid_value_error(format!("time value {} out of range", v)))?;
+                PgValue::Time(time)
+            }
+            Value::Timestamp(unit, v) => {
+                let micros = to_microseconds(unit, v)
+                    .ok_or_else(|| invalid_value_error(format!("timestamp value {} out of range", v)))?;
+                let seconds = micros.div_euclid(1_000_000);
+                let microseconds = micros.rem_euclid(1_000_000) as u32;
+                let datetime = DateTime::from_timestamp(seconds, microseconds * 1_000)
+                    .ok_or_else(|| invalid_value_error(format!("timestamp value {} out of range", v)))?;
+                // DuckDB 中 timestamptz 保存的是 UTC 时间, 按会话时区输出
+                if matches!(data_type, DataType::Timestamp(_, Some(_))) {
+                    PgValue::Timestamptz(datetime.with_timezone(&settings.time_zone).fixed_offset())
+                } else {
+                    PgValue::Timestamp(datetime.naive_utc())
+                }
+            }
+            Value::Interval { months, days, nanos } => {
+                // DuckDB 内部以微秒保存 interval, nanos 只是 Arrow 的表示方式
+                let interval = PgInterval::new(months, days, nanos.div_euclid(1_000));
+                PgValue::Interval(interval.with_style(settings.interval_style))
+            }
+            Value::List(items) | Value::Array(items) => {
+                PgValue::Array(PgArray::from_values(items, &list_element_field(data_type), settings)?)
+            }
+            value @ (Value::Struct(_) | Value::Map(_) | Value::Union(_)) => match FATHERDUCK_CONFIG.nested_format {
+                NestedFormat::Json => PgValue::Json(json_text(&value, data_type, settings)?),
+                NestedFormat::Jsonb => PgValue::Jsonb(json_text(&value, data_type, settings)?),
+                NestedFormat::Record => into_record(value, data_type, settings)?,
+            },
+        })
+    }
+
+    // 值对应的 PostgreSQL 类型, 用于 record 和数组里无法从列类型确定的元素
+    pub fn pg_type(&self) -> Type {
+        match self {
+            PgValue::Null => Type::UNKNOWN,
+            PgValue::Bool(_) => Type::BOOL,
+            PgValue::Int2(_) => Type::INT2,
+            PgValue::Int4(_) => Type::INT4,
+            PgValue::Int8(_) => Type::INT8,
+            PgValue::Float4(_) => Type::FLOAT4,
+            PgValue::Float8(_) => Type::FLOAT8,
+            PgValue::Numeric(_) | PgValue::NumericInt(_) => Type::NUMERIC,
+            PgValue::Text(_) => Type::VARCHAR,
+            PgValue::Bytea(_) => Type::BYTEA,
+            PgValue::Date(_) => Type::DATE,
+            PgValue::Time(_) => Type::TIME,
+            PgValue::TimeTz(_) => Type::TIMETZ,
+            PgValue::Timestamp(_) => Type::TIMESTAMP,
+            PgValue::Timestamptz(_) => Type::TIMESTAMPTZ,
+            PgValue::Interval(_) => Type::INTERVAL,
+            PgValue::Bit(_) => Type::VARBIT,
+            PgValue::Array(array) => into_pg_array_type(&array.element_type).unwrap_or(Type::TEXT_ARRAY),
+            PgValue::Json(_) => Type::JSON,
+            PgValue::Jsonb(_) => Type::JSONB,
+            PgValue::Uuid(_) => Type::UUID,
+            PgValue::Record(_) => Type::RECORD,
+        }
+    }
+
+    fn text(&self) -> Result<Option<String>, Box<dyn Error + Sync + Send>> {
+        let mut out = BytesMut::new();
+        Ok(match self.encode_text(&self.pg_type(), &mut out)? {
+            IsNull::Yes => None,
+            IsNull::No => Some(String::from_utf8_lossy(&out).into_owned()),
+        })
+    }
+
+    fn encode_text(&self, ty: &Type, out: &mut BytesMut) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        match self {
+            PgValue::Null => return Ok(IsNull::Yes),
+            PgValue::Bool(b) => out.put_slice(if *b { b"true" } else { b"false" }),
+            PgValue::Int2(i) => out.put_slice(i.to_string().as_bytes()),
+            PgValue::Int4(i) => out.put_slice(i.to_string().as_bytes()),
+            PgValue::Int8(i) => out.put_slice(i.to_string().as_bytes()),
+            PgValue::Float4(f) => out.put_slice(float_text(*f).as_bytes()),
+            PgValue::Float8(f) => out.put_slice(float_text(*f).as_bytes()),
+            PgValue::Numeric(d) => out.put_slice(d.to_string().as_bytes()),
+            PgValue::NumericInt(i) => return i.to_sql_text(ty, out),
+            PgValue::Text(t) => out.put_slice(t.as_bytes()),
+            PgValue::Bytea(b) => {
+                out.put_slice(b"\\x");
+                for byte in b {
+                    out.put_slice(format!("{:02x}", byte).as_bytes());
+                }
+            }
+            PgValue::Date(d) => out.put_slice(d.format("%Y-%m-%d").to_string().as_bytes()),
+            PgValue::Time(t) => out.put_slice(t.format("%H:%M:%S%.f").to_string().as_bytes()),
+            PgValue::TimeTz(t) => return t.to_sql_text(ty, out),
+            PgValue::Timestamp(t) => out.put_slice(t.format("%Y-%m-%d %H:%M:%S%.f").to_string().as_bytes()),
+            PgValue::Timestamptz(t) => out.put_slice(t.format("%Y-%m-%d %H:%M:%S%.f%:z").to_string().as_bytes()),
+            PgValue::Interval(i) => return i.to_sql_text(ty, out),
+            PgValue::Bit(b) => return b.to_sql_text(ty, out),
+            PgValue::Array(a) => return a.to_sql_text(ty, out),
+            PgValue::Json(j) | PgValue::Jsonb(j) => out.put_slice(j.as_bytes()),
+            PgValue::Uuid(u) => out.put_slice(uuid_text(u)?.as_bytes()),
+            PgValue::Record(fields) => {
+                // record 的文本格式: NULL 字段为空, 含有特殊字符的字段加双引号, 引号和反斜杠写两次
+                out.put_u8(b'(');
+                for (idx, (_, field)) in fields.iter().enumerate() {
+                    if idx > 0 {
+                        out.put_u8(b',');
+                    }
+                    if let Some(text) = field.text()? {
+                        let needs_quote = text.is_empty()
+                            || text.chars().any(|c| matches!(c, '(' | ')' | ',' | '"' | '\\') || c.is_whitespace());
+                        if needs_quote {
+                            out.put_u8(b'"');
+                            for c in text.chars() {
+                                if matches!(c, '"' | '\\') {
+                                    out.put_slice(c.to_string().as_bytes());
+                                }
+                                out.put_slice(c.to_string().as_bytes());
+                            }
+                            out.put_u8(b'"');
+                        } else {
+                            out.put_slice(text.as_bytes());
+                        }
+                    }
+                }
+                out.put_u8(b')');
+            }
+        }
+        Ok(IsNull::No)
+    }
+}
+
+// PostgreSQL 的浮点数文本格式用 Infinity / -Infinity / NaN 表示特殊值
+fn float_text<F: Into<f64> + ToString + Copy>(f: F) -> String {
+    let value: f64 = f.into();
+    if value.is_nan() {
+        "NaN".to_owned()
+    } else if value.is_infinite() {
+        if value > 0.0 { "Infinity".to_owned() } else { "-Infinity".to_owned() }
+    } else {
+        f.to_string()
+    }
+}
+
+impl ToSql for PgValue {
+    fn to_sql(&self, ty: &Type, out: &mut BytesMut) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        match self {
+            PgValue::Null => Ok(IsNull::Yes),
+            PgValue::Bool(b) => b.to_sql(ty, out),
+            PgValue::Int2(i) => i.to_sql(ty, out),
+            PgValue::Int4(i) => i.to_sql(ty, out),
+            PgValue::Int8(i) => i.to_sql(ty, out),
+            PgValue::Float4(f) => f.to_sql(ty, out),
+            PgValue::Float8(f) => f.to_sql(ty, out),
+            PgValue::Numeric(d) => d.to_sql(ty, out),
+            PgValue::NumericInt(i) => i.to_sql(ty, out),
+            PgValue::Text(t) => t.to_sql(ty, out),
+            PgValue::Bytea(b) => b.to_sql(ty, out),
+            PgValue::Date(d) => d.to_sql(ty, out),
+            PgValue::Time(t) => t.to_sql(ty, out),
+            PgValue::TimeTz(t) => t.to_sql(ty, out),
+            PgValue::Timestamp(t) => t.to_sql(ty, out),
+            PgValue::Timestamptz(t) => t.to_sql(ty, out),
+            PgValue::Interval(i) => i.to_sql(ty, out),
+            PgValue::Bit(b) => b.to_sql(ty, out),
+            PgValue::Array(a) => a.to_sql(ty, out),
+            PgValue::Json(j) => {
+                out.put_slice(j.as_bytes());
+                Ok(IsNull::No)
+            }
+            PgValue::Jsonb(j) => {
+                // jsonb 的二进制格式是版本号 1 加上 JSON 文本
+                out.put_u8(1);
+                out.put_slice(j.as_bytes());
+                Ok(IsNull::No)
+            }
+            PgValue::Uuid(u) => {
+                out.put_slice(u);
+                Ok(IsNull::No)
+            }
+            PgValue::Record(fields) => {
+                out.put_i32(fields.len() as i32);
+                for (field_type, field) in fields {
+                    out.put_u32(field_type.oid());
+                    let base = out.len();
+                    out.put_i32(0);
+                    match field.to_sql(field_type, out)? {
+                        IsNull::Yes => {
+                            out.truncate(base);
+                            out.put_i32(-1);
+                        }
+                        IsNull::No => {
+                            let len = (out.len() - base - 4) as i32;
+                            out[base..base + 4].copy_from_slice(&len.to_be_bytes());
+                        }
+                    }
+                }
+                Ok(IsNull::No)
+            }
+        }
+    }
+
+    // 值在转换时已经和列类型对应, 不再检查
+    fn accepts(_ty: &Type) -> bool {
+        true
+    }
+
+    to_sql_checked!();
+}
+
+impl ToSqlText for PgValue {
+    fn to_sql_text(&self, ty: &Type, out: &mut BytesMut) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        self.encode_text(ty, out)
+    }
+}
+
+// DuckDB 的 LIST / ARRAY 对应 PostgreSQL 的数组, 嵌套的 LIST 对应多维数组
+#[derive(Debug, Clone, PartialEq)]
+pub struct PgArray {
+    element_type: Type,
+    elements: Vec<PgValue>,
+}
+
+impl PgArray {
+    // 嵌套的 LIST 和外层共用同一个元素类型. 元素的 Arrow 类型未知时, 从第一个非 NULL 的元素推断
+    fn from_values(items: Vec<Value>, element_field: &Field, settings: &SessionSettings) -> PgWireResult<PgArray> {
+        let elements = items
+            .into_iter()
+            .map(|item| PgValue::from_field_value(item, element_field, settings))
+            .collect::<PgWireResult<Vec<PgValue>>>()?;
+        let element_type = match into_pg_field_type(element_field) {
+            Ok(Type::UNKNOWN) | Err(_) => elements
+                .iter()
+                .find(|element| **element != PgValue::Null)
+                .map(|element| element.pg_type())
+                .unwrap_or(Type::TEXT),
+            Ok(element_type) => element_type,
+        };
+        let element_type = match element_type.kind() {
+            Kind::Array(element_type) => element_type.clone(),
+            _ => element_type,
+        };
+        let array = PgArray { element_type, elements };
+        array.dimensions().map_err(invalid_value_error)?;
+        Ok(array)
+    }
+
+    // PostgreSQL 的多维数组必须是规则的, 每一层的子数组长度相同且不能为 NULL.
+    // 含有空的子数组时整体按空数组处理
+    fn dimensions(&self) -> Result<Vec<i32>, String> {
+        let mut dimensions = vec![self.elements.len() as i32];
+        let sub_arrays: Vec<&PgArray> = self
+            .elements
+            .iter()
+            .filter_map(|element| match element {
+                PgValue::Array(array) => Some(array),
+                _ => None,
+            })
+            .collect();
+        if !sub_arrays.is_empty() {
+            if sub_arrays.len() != self.elements.len() {
+                return Err("multidimensional arrays must have array expressions with matching dimensions".to_owned());
+            }
+            let sub_dimensions = sub_arrays[0].dimensions()?;
+            for sub_array in &sub_arrays[1..] {
+                if sub_array.dimensions()? != sub_dimensions {
+                    return Err("multidimensional arrays must have array expressions with matching dimensions".to_owned());
+                }
+            }
+            dimensions.extend(sub_dimensions);
+        }
+        if dimensions.contains(&0) {
+            return Ok(vec![]);
+        }
+        Ok(dimensions)
+    }
+
+    // 按行优先顺序展开所有维度后的元素
+    fn leaves(&self) -> Vec<&PgValue> {
+        self.elements
+            .iter()
+            .flat_map(|element| match element {
+                PgValue::Array(array) => array.leaves(),
+                element => vec![element],
+            })
+            .collect()
+    }
+
+    fn encode_text(&self, out: &mut BytesMut) -> Result<(), Box<dyn Error + Sync + Send>> {
+        out.put_u8(b'{');
+        for (idx, element) in self.elements.iter().enumerate() {
+            if idx > 0 {
+                out.put_u8(b',');
+            }
+            match element {
+                PgValue::Array(array) => array.encode_text(out)?,
+                element => {
+                    let mut text = BytesMut::new();
+                    match element.encode_text(&self.element_type, &mut text)? {
+                        IsNull::Yes => out.put_slice(b"NULL"),
+                        IsNull::No => push_array_element(out, &text),
+                    }
+                }
+            }
+        }
+        out.put_u8(b'}');
+        Ok(())
+    }
+}
+
+// 空串, NULL 以及包含分隔符, 引号, 反斜杠或空白的元素需要加双引号
+fn push_array_element(out: &mut BytesMut, text: &[u8]) {
+    let needs_quote = text.is_empty()
+        || text.eq_ignore_ascii_case(b"NULL")
+        || text.iter().any(|c| matches!(c, b'{' | b'}' | b',' | b'"' | b'\\') || c.is_ascii_whitespace());
+    if !needs_quote {
+        out.put_slice(text);
+        return;
+    }
+    out.put_u8(b'"');
+    for c in text {
+        if matches!(c, b'"' | b'\\') {
+            out.put_u8(b'\\');
+        }
+        out.put_u8(*c);
+    }
+    out.put_u8(b'"');
+}
+
+impl ToSql for PgArray {
+    fn to_sql(&self, _ty: &Type, out: &mut BytesMut) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        let dimensions = self.dimensions()?;
+        let leaves = if dimensions.is_empty() { vec![] } else { self.leaves() };
+        out.put_i32(dimensions.len() as i32);
+        out.put_i32(leaves.iter().any(|leaf| **leaf == PgValue::Null) as i32);
+        out.put_u32(self.element_type.oid());
+        for dimension in dimensions {
+            out.put_i32(dimension);
+            // 下标从 1 开始
+            out.put_i32(1);
+        }
+        for leaf in leaves {
+            let base = out.len();
+            out.put_i32(0);
+            match leaf.to_sql(&self.element_type, out)? {
+                IsNull::Yes => {
+                    out.truncate(base);
+                    out.put_i32(-1);
+                }
+                IsNull::No => {
+                    let len = (out.len() - base - 4) as i32;
+                    out[base..base + 4].copy_from_slice(&len.to_be_bytes());
+                }
+            }
+        }
+        Ok(IsNull::No)
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        matches!(ty.kind(), Kind::Array(_))
+    }
+
+    to_sql_checked!();
+}
+
+impl ToSqlText for PgArray {
+    fn to_sql_text(&self, _ty: &Type, out: &mut BytesMut) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        if self.dimensions()?.is_empty() {
+            out.put_slice(b"{}");
+        } else {
+            self.encode_text(out)?;
+        }
+        Ok(IsNull::No)
+    }
+}
+
+// 嵌套类型里的字段, 类型未知时返回 DataType::Null 的字段
+fn unknown_field() -> Field {
+    Field::new("", DataType::Null, true)
+}
+
+fn list_element_field(data_type: &DataType) -> Field {
+    match data_type {
+        DataType::List(field) | DataType::LargeList(field) | DataType::FixedSizeList(field, _) => field.as_ref().clone(),
+        _ => unknown_field(),
+    }
+}
+
+fn struct_field(data_type: &DataType, idx: usize) -> Field {
+    match data_type {
+        DataType::Struct(fields) => fields.get(idx).map(|field| field.as_ref().clone()).unwrap_or_else(unknown_field),
+        _ => unknown_field(),
+    }
+}
+
+// MAP 在 Arrow 中是 entries: STRUCT(key, value) 的列表
+fn map_entry_fields(data_type: &DataType) -> (Field, Field) {
+    match data_type {
+        DataType::Map(entries, _) => (struct_field(entries.data_type(), 0), struct_field(entries.data_type(), 1)),
+        _ => (unknown_field(), unknown_field()),
+    }
+}
+
+// STRUCT 转换成 record, MAP 转换成 (key, value) record 的数组, UNION 转换成只有当前成员的 record
+fn into_record(value: Value, data_type: &DataType, settings: &SessionSettings) -> PgWireResult<PgValue> {
+    let field = |value: Value, field: &Field| -> PgWireResult<(Type, PgValue)> {
+        let field_type = into_pg_field_type(field);
+        let field = PgValue::from_field_value(value, field, settings)?;
+        let field_type = match field_type {
+            Ok(Type::UNKNOWN) | Err(_) => field.pg_type(),
+            Ok(field_type) => field_type,
+        };
+        Ok((field_type, field))
+    };
+    Ok(match value {
+        Value::Struct(fields) => {
+            let fields = fields
+                .iter()
+                .enumerate()
+                .map(|(idx, (_, item))| field(item.clone(), &struct_field(data_type, idx)))
+                .collect::<PgWireResult<Vec<(Type, PgValue)>>>()?;
+            PgValue::Record(fields)
+        }
+        Value::Map(entries) => {
+            let (key_field, value_field) = map_entry_fields(data_type);
+            let elements = entries
+                .iter()
+                .map(|(key, item)| {
+                    Ok(PgValue::Record(vec![field(key.clone(), &key_field)?, field(item.clone(), &value_field)?]))
+                })
+                .collect::<PgWireResult<Vec<PgValue>>>()?;
+            PgValue::Array(PgArray { element_type: Type::RECORD, elements })
+        }
+        Value::Union(item) => PgValue::Record(vec![field(*item, &unknown_field())?]),
+        other => PgValue::from_value(other, data_type, settings)?,
+    })
+}
+
+fn json_text(value: &Value, data_type: &DataType, settings: &SessionSettings) -> PgWireResult<String> {
+    let mut out = String::new();
+    write_json(value, data_type, settings, &mut out)?;
+    Ok(out)
+}
+
+// 数字和布尔值写成 JSON 的原生类型, 其余标量写成 PostgreSQL 文本格式的字符串
+fn write_json(value: &Value, data_type: &DataType, settings: &SessionSettings, out: &mut String) -> PgWireResult<()> {
+    match value {
+        Value::Null => out.push_str("null"),
+        Value::Boolean(b) => out.push_str(if *b { "true" } else { "false" }),
+        Value::List(items) | Value::Array(items) => {
+            let element_field = list_element_field(data_type);
+            out.push('[');
+            for (idx, item) in items.iter().enumerate() {
+                if idx > 0 {
+                    out.push(',');
+                }
+                write_json_field(item, &element_field, settings, out)?;
+            }
+            out.push(']');
+        }
+        Value::Struct(fields) => {
+            out.push('{');
+            for (idx, (name, item)) in fields.iter().enumerate() {
+                if idx > 0 {
+                    out.push(',');
+                }
+                push_json_string(out, name);
+                out.push(':');
+                write_json_field(item, &struct_field(data_type, idx), settings, out)?;
+            }
+            out.push('}');
+        }
+        Value::Map(entries) => {
+            // JSON 对象的 key 只能是字符串
+            let (key_field, value_field) = map_entry_fields(data_type);
+            out.push('{');
+            for (idx, (key, item)) in entries.iter().enumerate() {
+                if idx > 0 {
+                    out.push(',');
+                }
+                let key = match key {
+                    Value::Text(key) => key.clone(),
+                    key => field_text(key, &key_field, settings)?.unwrap_or_else(|| "null".to_owned()),
+                };
+                push_json_string(out, &key);
+                out.push(':');
+                write_json_field(item, &value_field, settings, out)?;
+            }
+            out.push('}');
+        }
+        Value::Union(item) => write_json(item, &DataType::Null, settings, out)?,
+        Value::Enum(text) => push_json_string(out, text),
+        Value::TinyInt(_) | Value::SmallInt(_) | Value::Int(_) | Value::BigInt(_) | Value::HugeInt(_)
+        | Value::UTinyInt(_) | Value::USmallInt(_) | Value::UInt(_) | Value::UBigInt(_) | Value::Decimal(_) => {
+            out.push_str(&json_scalar_text(value, data_type, settings)?.unwrap_or_default());
+        }
+        Value::Float(f) if f.is_finite() => out.push_str(&f.to_string()),
+        Value::Double(f) if f.is_finite() => out.push_str(&f.to_string()),
+        value => {
+            let text = json_scalar_text(value, data_type, settings)?.unwrap_or_default();
+            push_json_string(out, &text);
+        }
+    }
+    Ok(())
+}
+
+// JSON 字段的值本身就是 JSON 文本, 原样写入. 其余扩展类型按字段转换, HUGEINT 写成数字
+fn write_json_field(value: &Value, field: &Field, settings: &SessionSettings, out: &mut String) -> PgWireResult<()> {
+    match (value, extension_name(field)) {
+        (Value::Text(text), Some(ARROW_JSON_EXTENSION)) => out.push_str(text),
+        (Value::Blob(_), Some(ARROW_HUGEINT_EXTENSION | ARROW_UHUGEINT_EXTENSION)) => {
+            out.push_str(&field_text(value, field, settings)?.unwrap_or_default())
+        }
+        (Value::Blob(_), Some(_)) => push_json_string(out, &field_text(value, field, settings)?.unwrap_or_default()),
+        (value, _) => write_json(value, field.data_type(), settings, out)?,
+    }
+    Ok(())
+}
+
+fn json_scalar_text(value: &Value, data_type: &DataType, settings: &SessionSettings) -> PgWireResult<Option<String>> {
+    PgValue::from_value(value.clone(), data_type, settings)?
+        .text()
+        .map_err(|e| invalid_value_error(e.to_string()))
+}
+
+fn field_text(value: &Value, field: &Field, settings: &SessionSettings) -> PgWireResult<Option<String>> {
+    PgValue::from_field_value(value.clone(), field, settings)?
+        .text()
+        .map_err(|e| invalid_value_error(e.to_string()))
+}
+
+fn push_json_string(out: &mut String, text: &str) {
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+// 标量参数统一转换成 PostgreSQL 的文本格式, 作为 VARCHAR 绑定后由 DuckDB 转换成参数的实际类型
+pub fn text_param(raw: &[u8], format: FieldFormat, param_type: &Type) -> PgWireResult<String> {
+    match format {
+        FieldFormat::Text => String::from_utf8(raw.to_vec()).map_err(|e| e.to_string()),
+        FieldFormat::Binary => decode_binary_element(param_type, raw).map_err(|e| e.to_string()),
+    }
+    .map_err(|e| invalid_value_error(format!("invalid {} parameter: {}", param_type, e)))
+}
+
+// COPY 的二进制格式的值按列的类型解码成 DuckDB 的值, 由 Appender 直接写入.
+// Appender 不支持 DECIMAL 等类型, 这些值和参数一样先转换成文本
+pub fn binary_value(raw: &[u8], column_type: &Type) -> PgWireResult<Value> {
+    let ty = column_type;
+    let value = || -> Result<Value, Box<dyn Error + Sync + Send>> {
+        Ok(match *ty {
+            Type::BOOL => Value::Boolean(bool::from_sql(ty, raw)?),
+            Type::INT2 => Value::SmallInt(i16::from_sql(ty, raw)?),
+            Type::INT4 => Value::Int(i32::from_sql(ty, raw)?),
+            Type::INT8 => Value::BigInt(i64::from_sql(ty, raw)?),
+            Type::FLOAT4 => Value::Float(f32::from_sql(ty, raw)?),
+            Type::FLOAT8 => Value::Double(f64::from_sql(ty, raw)?),
+            Type::DATE => Value::Date32((NaiveDate::from_sql(ty, raw)? - BASE_DATE).num_days() as i32),
+            Type::TIME => {
+                let micros = (NaiveTime::from_sql(ty, raw)? - NaiveTime::MIN).num_microseconds().ok_or("time out of range")?;
+                Value::Time64(TimeUnit::Microsecond, micros)
+            }
+            Type::TIMESTAMP => Value::Timestamp(TimeUnit::Microsecond, NaiveDateTime::from_sql(ty, raw)?.and_utc().timestamp_micros()),
+            Type::INTERVAL => PgInterval::from_sql(ty, raw)?.into(),
+            _ => Value::Text(decode_binary_element(ty, raw)?),
+        })
+    };
+    value().map_err(|e| invalid_value_error(format!("invalid {} value: {}", column_type, e)))
+}
+
+// bytea 的文本格式是 \x 开头的十六进制, 或者用反斜杠转义的旧格式
+pub fn bytea_param(raw: &[u8], format: FieldFormat) -> PgWireResult<Vec<u8>> {
+    if format == FieldFormat::Binary {
+        return Ok(raw.to_vec());
+    }
+    let invalid = || invalid_value_error("invalid input syntax for type bytea".to_owned());
+    if let Some(hex) = raw.strip_prefix(b"\\x") {
+        let hex = std::str::from_utf8(hex).map_err(|_| invalid())?;
+        let digits: Vec<char> = hex.chars().filter(|c| !c.is_whitespace()).collect();
+        return digits
+            .chunks(2)
+            .map(|pair| match pair {
+                [high, low] => u8::from_str_radix(&format!("{}{}", high, low), 16).map_err(|_| invalid()),
+                _ => Err(invalid()),
+            })
+            .collect();
+    }
+    let mut bytes = Vec::with_capacity(raw.len());
+    let mut i = 0;
+    while i < raw.len() {
+        match (raw[i], raw.get(i + 1)) {
+            (b'\\', Some(b'\\')) => {
+                bytes.push(b'\\');
+                i += 2;
+            }
+            (b'\\', _) => {
+                let octal = raw.get(i + 1..i + 4).and_then(|octal| std::str::from_utf8(octal).ok()).ok_or_else(invalid)?;
+                bytes.push(u8::from_str_radix(octal, 8).map_err(|_| invalid())?);
+                i += 4;
+            }
+            (byte, _) => {
+                bytes.push(byte);
+                i += 1;
+            }
+        }
+    }
+    Ok(bytes)
+}
+
+fn fixed_bytes<const N: usize>(raw: &[u8], type_name: &str) -> PgWireResult<[u8; N]> {
+    raw.try_into()
+        .map_err(|_| invalid_value_error(format!("invalid {} value of {} bytes", type_name, raw.len())))
+}
+
+fn uuid_text(raw: &[u8]) -> Result<String, Box<dyn Error + Sync + Send>> {
+    if raw.len() != 16 {
+        return Err("invalid uuid length".into());
+    }
+    let hex: String = raw.iter().map(|byte| format!("{:02x}", byte)).collect();
+    Ok(format!("{}-{}-{}-{}-{}", &hex[0..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..32]))
+}
+
+// 位串参数转换成 '0101' 的形式, 由 DuckDB 转换成 BIT
+pub fn bit_param(raw: &[u8], format: FieldFormat, bit_type: &Type) -> PgWireResult<String> {
+    let bit_string = match format {
+        FieldFormat::Text => std::str::from_utf8(raw).ok().and_then(BitString::parse),
+        FieldFormat::Binary => BitString::from_sql(bit_type, raw).ok(),
+    };
+    bit_string
+        .map(|bit_string| bit_string.text())
+        .ok_or_else(|| invalid_value_error("invalid bit string parameter".to_owned()))
+}
+
+// json 参数的文本和二进制格式都是 JSON 文本, 二进制的 jsonb 前面多了一个版本号
+pub fn json_param(raw: &[u8], format: FieldFormat, json_type: &Type) -> PgWireResult<String> {
+    let raw = match (format, json_type) {
+        (FieldFormat::Binary, &Type::JSONB) => match raw.split_first() {
+            Some((1, text)) => text,
+            _ => return Err(invalid_value_error("unsupported jsonb version number".to_owned())),
+        },
+        _ => raw,
+    };
+    String::from_utf8(raw.to_vec()).map_err(|e| invalid_value_error(format!("invalid json parameter: {}", e)))
+}
+
+// DuckDB 无法直接绑定 LIST 参数, 把 PostgreSQL 数组参数转换成 DuckDB 的 list 字面量,
+// 作为 VARCHAR 绑定后由 DuckDB 转换成参数的实际类型
+pub fn array_param_literal(raw: &[u8], format: FieldFormat, array_type: &Type) -> PgWireResult<String> {
+    let item = match format {
+        FieldFormat::Text => std::str::from_utf8(raw)
+            .map_err(|e| e.to_string())
+            .and_then(parse_text_array),
+        FieldFormat::Binary => parse_binary_array(raw, array_type),
+    }
+    .map_err(|e| invalid_value_error(format!("malformed array literal: {}", e)))?;
+    let mut literal = String::new();
+    item.write_literal(&mut literal);
+    Ok(literal)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ArrayItem {
+    Null,
+    Element(String),
+    Array(Vec<ArrayItem>),
+}
+
+impl ArrayItem {
+    fn write_literal(&self, out: &mut String) {
+        match self {
+            ArrayItem::Null => out.push_str("NULL"),
+            ArrayItem::Element(text) => {
+                let needs_quote = text.is_empty()
+                    || text.eq_ignore_ascii_case("NULL")
+                    || text.chars().any(|c| matches!(c, '[' | ']' | '{' | '}' | ',' | '"' | '\'' | '\\') || c.is_whitespace());
+                if needs_quote {
+                    out.push('"');
+                    for c in text.chars() {
+                        if matches!(c, '"' | '\\') {
+                            out.push('\\');
+                        }
+                        out.push(c);
+                    }
+                    out.push('"');
+                } else {
+                    out.push_str(text);
+                }
+            }
+            ArrayItem::Array(items) => {
+                out.push('[');
+                for (idx, item) in items.iter().enumerate() {
+                    if idx > 0 {
+                        out.push_str(", ");
+                    }
+                    item.write_literal(out);
+                }
+                out.push(']');
+            }
+        }
+    }
+}
+
+// 解析 PostgreSQL 数组的文本格式, 例如 {1,2,NULL} 或 {{"a b",c},{d,e}}
+fn parse_text_array(text: &str) -> Result<ArrayItem, String> {
+    let text = text.trim();
+    // 忽略可选的下标声明, 例如 [1:2]={1,2}
+    let text = match text.strip_prefix('[') {
+        Some(_) => text.split_once('=').map(|(_, rest)| rest.trim()).ok_or("missing \"=\" after array dimensions")?,
+        None => text,
+    };
+    let chars: Vec<char> = text.chars().collect();
+    let mut pos = 0;
+    let item = parse_text_array_at(&chars, &mut pos)?;
+    if pos != chars.len() {
+        return Err("junk after closing right brace".to_owned());
+    }
+    Ok(item)
+}
+
+fn parse_text_array_at(chars: &[char], pos: &mut usize) -> Result<ArrayItem, String> {
+    if chars.get(*pos) != Some(&'{') {
+        return Err("array value must start with \"{\"".to_owned());
+    }
+    *pos += 1;
+    let mut items = Vec::new();
+    skip_whitespace(chars, pos);
+    if chars.get(*pos) == Some(&'}') {
+        *pos += 1;
+        return Ok(ArrayItem::Array(items));
+    }
+    loop {
+        skip_whitespace(chars, pos);
+        let item = match chars.get(*pos) {
+            Some('{') => parse_text_array_at(chars, pos)?,
+            Some('"') => {
+                *pos += 1;
+                let mut text = String::new();
+                loop {
+                    match chars.get(*pos) {
+                        Some('"') => break,
+                        Some('\\') => {
+                            *pos += 1;
+                            text.push(*chars.get(*pos).ok_or("unexpected end of input")?);
+                        }
+                        Some(c) => text.push(*c),
+                        None => return Err("unexpected end of input".to_owned()),
+                    }
+                    *pos += 1;
+                }
+                *pos += 1;
+                ArrayItem::Element(text)
+            }
+            Some(_) => {
+                let mut text = String::new();
+                while let Some(c) = chars.get(*pos) {
+                    match c {
+                        ',' | '}' => break,
+                        '\\' => {
+                            *pos += 1;
+                            text.push(*chars.get(*pos).ok_or("unexpected end of input")?);
+                        }
+                        c => text.push(*c),
+                    }
+                    *pos += 1;
+                }
+                let text = text.trim_end().to_owned();
+                if text.eq_ignore_ascii_case("NULL") {
+                    ArrayItem::Null
+                } else {
+                    ArrayItem::Element(text)
+                }
+            }
+            None => return Err("unexpected end of input".to_owned()),
+        };
+        items.push(item);
+        skip_whitespace(chars, pos);
+        match chars.get(*pos) {
+            Some(',') => *pos += 1,
+            Some('}') => {
+                *pos += 1;
+                return Ok(ArrayItem::Array(items));
+            }
+            _ => return Err("unexpected array element".to_owned()),
+        }
+    }
+}
+
+fn skip_whitespace(chars: &[char], pos: &mut usize) {
+    while chars.get(*pos).is_some_and(|c| c.is_whitespace()) {
+        *pos += 1;
+    }
+}
+
+// 解析 PostgreSQL 数组的二进制格式: 维数, NULL 标记, 元素类型, 各维长度和下界, 之后是展开后的元素
+fn parse_binary_array(mut raw: &[u8], array_type: &Type) -> Result<ArrayItem, String> {
+    if raw.len() < 12 {
+        return Err("insufficient data".to_owned());
+    }
+    let ndim = raw.get_i32();
+    let _has_null = raw.get_i32();
+    let element_oid = raw.get_u32();
+    let element_type = Type::from_oid(element_oid)
+        .or_else(|| match array_type.kind() {
+            Kind::Array(element_type) => Some(element_type.clone()),
+            _ => None,
+        })
+        .ok_or_else(|| format!("unknown element type {}", element_oid))?;
+    if ndim < 0 || raw.len() < ndim as usize * 8 {
+        return Err("insufficient data".to_owned());
+    }
+    let dimensions: Vec<usize> = (0..ndim)
+        .map(|_| {
+            let dimension = raw.get_i32();
+            let _lower_bound = raw.get_i32();
+            dimension.max(0) as usize
+        })
+        .collect();
+    let count = if dimensions.is_empty() { 0 } else { dimensions.iter().product() };
+    let mut elements = Vec::with_capacity(count);
+    for _ in 0..count {
+        if raw.len() < 4 {
+            return Err("insufficient data".to_owned());
+        }
+        let len = raw.get_i32();
+        if len < 0 {
+            elements.push(ArrayItem::Null);
+            continue;
+        }
+        let len = len as usize;
+        if raw.len() < len {
+            return Err("insufficient data".to_owned());
+        }
+        let text = decode_binary_element(&element_type, &raw[..len]).map_err(|e| e.to_string())?;
+        elements.push(ArrayItem::Element(text));
+        raw.advance(len);
+    }
+    Ok(nest_array_items(elements, &dimensions))
+}
+
+// 把展开的元素按维度重新组织成嵌套的数组
+fn nest_array_items(elements: Vec<ArrayItem>, dimensions: &[usize]) -> ArrayItem {
+    match dimensions {
+        [] | [_] => ArrayItem::Array(elements),
+        [_, rest @ ..] => {
+            let chunk: usize = rest.iter().product();
+            let mut elements = elements.into_iter();
+            let items = (0..elements.len() / chunk.max(1))
+                .map(|_| nest_array_items(elements.by_ref().take(chunk).collect(), rest))
+                .collect();
+            ArrayItem::Array(items)
+        }
+    }
+}
+
+// 二进制格式的数组元素先转换成文本, 和文本格式的参数走相同的路径
+fn decode_binary_element(ty: &Type, raw: &[u8]) -> Result<String, Box<dyn Error + Sync + Send>> {
+    Ok(match *ty {
+        Type::BOOL => bool::from_sql(ty, raw)?.to_string(),
+        Type::CHAR => i8::from_sql(ty, raw)?.to_string(),
+        Type::INT2 => i16::from_sql(ty, raw)?.to_string(),
+        Type::INT4 => i32::from_sql(ty, raw)?.to_string(),
+        Type::INT8 => i64::from_sql(ty, raw)?.to_string(),
+        Type::FLOAT4 => float_text(f32::from_sql(ty, raw)?),
+        Type::FLOAT8 => float_text(f64::from_sql(ty, raw)?),
+        Type::NUMERIC => Decimal::from_sql(ty, raw)?.to_string(),
+        Type::TEXT | Type::VARCHAR | Type::BPCHAR | Type::NAME => String::from_sql(ty, raw)?,
+        Type::DATE => NaiveDate::from_sql(ty, raw)?.to_string(),
+        Type::TIME => NaiveTime::from_sql(ty, raw)?.to_string(),
+        Type::TIMETZ => PgTimeTz::from_sql(ty, raw)?.encode_text(),
+        Type::TIMESTAMP => NaiveDateTime::from_sql(ty, raw)?.to_string(),
+        Type::TIMESTAMPTZ => DateTime::<Utc>::from_sql(ty, raw)?.to_rfc3339(),
+        Type::BIT | Type::VARBIT => BitString::from_sql(ty, raw)?.text(),
+        Type::UUID => uuid_text(raw)?,
+        Type::INTERVAL => PgInterval::from_sql(ty, raw)?.encode_text(),
+        Type::JSON => String::from_utf8(raw.to_vec())?,
+        Type::JSONB => match raw.split_first() {
+            Some((1, text)) => String::from_utf8(text.to_vec())?,
+            _ => return Err("unsupported jsonb version number".into()),
+        },
+        _ => return Err(format!("binary array element type {} is not supported", ty).into()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use duckdb::arrow::array::{DictionaryArray, Int32Array, ListArray, StringArray, StructArray};
+    use duckdb::arrow::datatypes::{Field, Fields};
+
+    use super::*;
+
+    // DuckDB 开启 arrow_lossless_conversion 时导出的扩展类型字段
+    fn extension_field(name: &str, data_type: DataType, extension: &str) -> Field {
+        Field::new(name, data_type, true).with_metadata(HashMap::from([(
+            ARROW_EXTENSION_NAME.to_owned(),
+            extension.to_owned(),
+        )]))
+    }
+
+    fn json_field(name: &str) -> Field {
+        extension_field(name, DataType::Utf8, ARROW_JSON_EXTENSION)
+    }
+
+    fn uuid_field(name: &str) -> Field {
+        extension_field(name, DataType::FixedSizeBinary(16), ARROW_UUID_EXTENSION)
+    }
+
+    fn time_tz_field(name: &str) -> Field {
+        extension_field(name, DataType::FixedSizeBinary(8), ARROW_TIME_TZ_EXTENSION)
+    }
+
+    fn hugeint_field(name: &str) -> Field {
+        extension_field(name, DataType::FixedSizeBinary(16), ARROW_HUGEINT_EXTENSION)
+    }
+
+    fn uhugeint_field(name: &str) -> Field {
+        extension_field(name, DataType::FixedSizeBinary(16), ARROW_UHUGEINT_EXTENSION)
+    }
+
+    fn encode_numeric(value: i128) -> Vec<u8> {
+        let mut out = BytesMut::new();
+        NumericInt::from(value).to_sql(&Type::NUMERIC, &mut out).unwrap();
+        out.to_vec()
+    }
+
+    #[test]
+    fn test_numeric_int_binary() {
+        assert_eq!(encode_numeric(0), vec![0, 0, 0, 0, 0, 0, 0, 0]);
+        // 12345678 = 1234 * 10000 + 5678
+        assert_eq!(encode_numeric(12345678), vec![0, 2, 0, 1, 0, 0, 0, 0, 0x04, 0xD2, 0x16, 0x2E]);
+        // -20000 = -2 * 10000, 末尾的 0 被去掉
+        assert_eq!(encode_numeric(-20000), vec![0, 1, 0, 1, 0x40, 0, 0, 0, 0, 2]);
+        // u128::MAX 有 39 位, 最高的一组是 340
+        let mut out = BytesMut::new();
+        NumericInt::from(u128::MAX).to_sql(&Type::NUMERIC, &mut out).unwrap();
+        assert_eq!(out[..10], [0, 10, 0, 9, 0, 0, 0, 0, 0x01, 0x54]);
+    }
+
+    #[test]
+    fn test_numeric_int_text() {
+        let mut out = BytesMut::new();
+        NumericInt::from(i128::MIN).to_sql_text(&Type::NUMERIC, &mut out).unwrap();
+        assert_eq!(out.as_ref(), i128::MIN.to_string().as_bytes());
+        let mut out = BytesMut::new();
+        NumericInt::from(u128::MAX).to_sql_text(&Type::NUMERIC, &mut out).unwrap();
+        assert_eq!(out.as_ref(), u128::MAX.to_string().as_bytes());
+    }
+
+    // 按列的 Arrow 类型确定 PostgreSQL 类型, 再用这个类型做二进制编码
+    fn encode_binary(value: Value, data_type: DataType) -> (Type, Vec<u8>) {
+        let pg_type = into_pg_type(&data_type).unwrap();
+        let mut out = BytesMut::new();
+        PgValue::from_value(value, &data_type, &settings()).unwrap().to_sql(&pg_type, &mut out).unwrap();
+        (pg_type, out.to_vec())
+    }
+
+    #[test]
+    fn test_integer_binary() {
+        assert_eq!(encode_binary(Value::TinyInt(-128), DataType::Int8), (Type::INT2, (-128i16).to_be_bytes().to_vec()));
+        assert_eq!(encode_binary(Value::TinyInt(127), DataType::Int8), (Type::INT2, 127i16.to_be_bytes().to_vec()));
+        assert_eq!(encode_binary(Value::UTinyInt(255), DataType::UInt8), (Type::INT2, 255i16.to_be_bytes().to_vec()));
+        assert_eq!(encode_binary(Value::USmallInt(u16::MAX), DataType::UInt16), (Type::INT4, 65535i32.to_be_bytes().to_vec()));
+        assert_eq!(encode_binary(Value::UInt(u32::MAX), DataType::UInt32), (Type::INT8, 4294967295i64.to_be_bytes().to_vec()));
+        assert_eq!(encode_binary(Value::UBigInt(u64::MAX), DataType::UInt64), (Type::NUMERIC, encode_numeric(u64::MAX as i128)));
+    }
+
+    // 二进制编码的结果按 client 的方式解码后, 和文本编码的结果一致
+    #[test]
+    fn test_binary_round_trip() {
+        let time = NaiveTime::from_hms_micro_opt(3, 4, 5, 123456).unwrap();
+        let timestamp = NaiveDate::from_ymd_opt(2024, 1, 2).unwrap().and_time(time);
+        let values = vec![
+            PgValue::Bool(true),
+            PgValue::Int2(i16::MIN),
+            PgValue::Int4(i32::MAX),
+            PgValue::Int8(i64::MIN),
+            PgValue::Float4(1.5),
+            PgValue::Float8(f64::NEG_INFINITY),
+            PgValue::Numeric(Decimal::new(-12345, 3)),
+            PgValue::NumericInt(NumericInt::from(12345678901234567890i128)),
+            PgValue::Text("héllo".to_owned()),
+            PgValue::Date(NaiveDate::from_ymd_opt(1969, 12, 31).unwrap()),
+            PgValue::Time(time),
+            PgValue::TimeTz(PgTimeTz { microseconds: 41_400_500_000, offset: -7200 }),
+            PgValue::Timestamp(timestamp),
+            PgValue::Interval(PgInterval::new(14, -3, 4_000_001)),
+            PgValue::Bit(BitString::parse("1011").unwrap()),
+            PgValue::Json(r#"{"a":[1,2]}"#.to_owned()),
+            PgValue::Jsonb(r#"{"a":[1,2]}"#.to_owned()),
+            PgValue::Uuid(*b"\x00\x01\x02\x03\x04\x05\x06\x07\x08\x09\x0a\x0b\x0c\x0d\x0e\x0f"),
+        ];
+        for value in values {
+            let ty = value.pg_type();
+            let mut out = BytesMut::new();
+            value.to_sql(&ty, &mut out).unwrap();
+            assert_eq!(decode_binary_element(&ty, &out).unwrap(), value.text().unwrap().unwrap(), "{}", ty);
+        }
+
+        let mut out = BytesMut::new();
+        PgValue::Bytea(vec![0, 255]).to_sql(&Type::BYTEA, &mut out).unwrap();
+        assert_eq!(Vec::<u8>::from_sql(&Type::BYTEA, &out).unwrap(), vec![0, 255]);
+        let timestamptz = timestamp.and_utc().with_timezone(&FixedOffset::east_opt(3600).unwrap());
+        let mut out = BytesMut::new();
+        PgValue::Timestamptz(timestamptz).to_sql(&Type::TIMESTAMPTZ, &mut out).unwrap();
+        assert_eq!(DateTime::<Utc>::from_sql(&Type::TIMESTAMPTZ, &out).unwrap(), timestamp.and_utc());
+    }
+
+    #[test]
+    fn test_time_tz() {
+        // DuckDB 中 '11:30:00-02:00' 的 dtime_tz_t
+        let bits = (41_400_000_000u64 << 24) | (57599 + 7200);
+        let field = time_tz_field("t");
+        assert_eq!(into_pg_field_type(&field).unwrap(), Type::TIMETZ);
+        let value = PgValue::from_field_value(Value::Blob(bits.to_le_bytes().to_vec()), &field, &settings()).unwrap();
+        assert_eq!(value.text().unwrap().unwrap(), "11:30:00-02");
+        let mut out = BytesMut::new();
+        value.to_sql(&Type::TIMETZ, &mut out).unwrap();
+        assert_eq!(out.to_vec(), [&41_400_000_000i64.to_be_bytes()[..], &7200i32.to_be_bytes()].concat());
+
+        let time_tz = PgTimeTz { microseconds: 1_500, offset: 5 * 3600 + 30 * 60 + 15 };
+        assert_eq!(time_tz.encode_text(), "00:00:00.0015+05:30:15");
+    }
+
+    fn interval_text(months: i32, days: i32, microseconds: i64, style: IntervalStyle) -> String {
+        PgInterval::new(months, days, microseconds).with_style(style).encode_text()
+    }
+
+    #[test]
+    fn test_interval_postgres_style() {
+        let style = IntervalStyle::Postgres;
+        assert_eq!(interval_text(0, 0, 0, style), "00:00:00");
+        assert_eq!(interval_text(14, 3, 14_706_789_000, style), "1 year 2 mons 3 days 04:05:06.789");
+        assert_eq!(interval_text(-14, 3, -14_706_000_000, style), "-1 years -2 mons +3 days -04:05:06");
+        assert_eq!(interval_text(0, 1, 0, style), "1 day");
+    }
+
+    #[test]
+    fn test_interval_other_styles() {
+        assert_eq!(
+            interval_text(14, 3, 14_706_789_000, IntervalStyle::PostgresVerbose),
+            "@ 1 year 2 mons 3 days 4 hours 5 mins 6.789 secs"
+        );
+        assert_eq!(interval_text(0, -1, -1_000_000, IntervalStyle::PostgresVerbose), "@ 1 day 1 sec ago");
+        assert_eq!(interval_text(14, 0, 0, IntervalStyle::SqlStandard), "1-2");
+        assert_eq!(interval_text(0, 3, 14_706_000_000, IntervalStyle::SqlStandard), "3 4:05:06");
+        assert_eq!(interval_text(14, -3, 14_706_000_000, IntervalStyle::SqlStandard), "+1-2 -3 +4:05:06");
+        assert_eq!(interval_text(14, 3, 14_706_500_000, IntervalStyle::Iso8601), "P1Y2M3DT4H5M6.5S");
+        assert_eq!(interval_text(0, 0, 0, IntervalStyle::Iso8601), "PT0S");
+    }
+
+    #[test]
+    fn test_interval_binary() {
+        let interval = PgInterval::new(14, 3, 1_500_000);
+        let mut out = BytesMut::new();
+        interval.to_sql(&Type::INTERVAL, &mut out).unwrap();
+        assert_eq!(PgInterval::from_sql(&Type::INTERVAL, &out).unwrap(), interval);
+    }
+
+    fn settings() -> SessionSettings {
+        SessionSettings { time_zone: Tz::UTC, interval_style: IntervalStyle::Postgres }
+    }
+
+    fn list_type(element_type: DataType) -> DataType {
+        DataType::List(Arc::new(Field::new("item", element_type, true)))
+    }
+
+    fn array_text(value: Value) -> Result<String, String> {
+        let data_type = match &value {
+            Value::List(items) if matches!(items.first(), Some(Value::List(_) | Value::Array(_))) => {
+                list_type(list_type(DataType::Int32))
+            }
+            _ => list_type(DataType::Int32),
+        };
+        let array = PgValue::from_value(value, &data_type, &settings()).map_err(|e| e.to_string())?;
+        let mut out = BytesMut::new();
+        array.to_sql_text(&Type::INT4_ARRAY, &mut out).map_err(|e| e.to_string())?;
+        Ok(String::from_utf8(out.to_vec()).unwrap())
+    }
+
+    #[test]
+    fn test_array_text() {
+        assert_eq!(array_text(Value::List(vec![Value::Int(1), Value::Null, Value::Int(3)])).unwrap(), "{1,NULL,3}");
+        assert_eq!(array_text(Value::List(vec![])).unwrap(), "{}");
+        let nested = Value::List(vec![
+            Value::List(vec![Value::Int(1), Value::Int(2)]),
+            Value::Array(vec![Value::Int(3), Value::Int(4)]),
+        ]);
+        assert_eq!(array_text(nested).unwrap(), "{{1,2},{3,4}}");
+        let ragged = Value::List(vec![Value::List(vec![Value::Int(1)]), Value::List(vec![])]);
+        assert!(array_text(ragged).is_err());
+
+        let strings = Value::List(vec![Value::Text("a b".to_owned()), Value::Text("NULL".to_owned()), Value::Text("c\"d".to_owned())]);
+        let array = PgValue::from_value(strings, &list_type(DataType::Utf8), &settings()).unwrap();
+        let mut out = BytesMut::new();
+        array.to_sql_text(&Type::VARCHAR_ARRAY, &mut out).unwrap();
+        assert_eq!(out.as_ref(), br#"{"a b","NULL","c\"d"}"#);
+    }
+
+    #[test]
+    fn test_array_binary() {
+        let nested = Value::List(vec![Value::List(vec![Value::Int(1), Value::Null])]);
+        let array = PgValue::from_value(nested, &list_type(list_type(DataType::Int32)), &settings()).unwrap();
+        let mut out = BytesMut::new();
+        array.to_sql(&Type::INT4_ARRAY, &mut out).unwrap();
+        let expected: Vec<u8> = [
+            &2i32.to_be_bytes()[..], &1i32.to_be_bytes(), &Type::INT4.oid().to_be_bytes(),
+            &1i32.to_be_bytes(), &1i32.to_be_bytes(), &2i32.to_be_bytes(), &1i32.to_be_bytes(),
+            &4i32.to_be_bytes(), &1i32.to_be_bytes(), &(-1i32).to_be_bytes(),
+        ]
+        .concat();
+        assert_eq!(out.to_vec(), expected);
+
+        let literal = array_param_literal(&expected, FieldFormat::Binary, &Type::INT4_ARRAY).unwrap();
+        assert_eq!(literal, "[[1, NULL]]");
+    }
+
+    fn nested_value() -> (Value, DataType) {
+        let fields = Fields::from(vec![
+            Field::new("id", DataType::Int32, true),
+            Field::new("name", DataType::Utf8, true),
+            Field::new("tags", list_type(DataType::Utf8), true),
+        ]);
+        let value = Value::Struct(OrderedMap::from(vec![
+            ("id".to_owned(), Value::Int(1)),
+            ("name".to_owned(), Value::Text("a \"b\"".to_owned())),
+            ("tags".to_owned(), Value::List(vec![Value::Text("x".to_owned()), Value::Null])),
+        ]));
+        (value, DataType::Struct(fields))
+    }
+
+    #[test]
+    fn test_nested_json() {
+        let (value, data_type) = nested_value();
+        assert_eq!(json_text(&value, &data_type, &settings()).unwrap(), r#"{"id":1,"name":"a \"b\"","tags":["x",null]}"#);
+
+        let map = Value::Map(OrderedMap::from(vec![(Value::Int(1), Value::Double(f64::NAN)), (Value::Int(2), Value::Null)]));
+        assert_eq!(json_text(&map, &DataType::Null, &settings()).unwrap(), r#"{"1":"NaN","2":null}"#);
+        assert_eq!(json_text(&Value::Union(Box::new(Value::Boolean(true))), &DataType::Null, &settings()).unwrap(), "true");
+    }
+
+    #[test]
+    fn test_nested_record() {
+        let (value, data_type) = nested_value();
+        let record = into_record(value, &data_type, &settings()).unwrap();
+        let mut out = BytesMut::new();
+        record.to_sql_text(&Type::RECORD, &mut out).unwrap();
+        assert_eq!(out.as_ref(), br#"(1,"a ""b""","{x,NULL}")"#);
+
+        let mut out = BytesMut::new();
+        into_record(Value::Union(Box::new(Value::Int(7))), &DataType::Null, &settings())
+            .unwrap()
+            .to_sql(&Type::RECORD, &mut out)
+            .unwrap();
+        let expected: Vec<u8> =
+            [&1i32.to_be_bytes()[..], &Type::INT4.oid().to_be_bytes(), &4i32.to_be_bytes(), &7i32.to_be_bytes()].concat();
+        assert_eq!(out.to_vec(), expected);
+    }
+
+    #[test]
+    fn test_json_field() {
+        let json = json_field("j");
+        assert_eq!(into_pg_field_type(&json).unwrap(), Type::JSON);
+        let value = PgValue::from_field_value(Value::Text(r#"{"a":1}"#.to_owned()), &json, &settings()).unwrap();
+        assert_eq!(value, PgValue::Json(r#"{"a":1}"#.to_owned()));
+
+        let list = DataType::List(Arc::new(json_field("item")));
+        assert_eq!(into_pg_type(&list).unwrap(), Type::JSON_ARRAY);
+        let fields = Fields::from(vec![json_field("j"), Field::new("s", DataType::Utf8, true)]);
+        let value = Value::Struct(OrderedMap::from(vec![
+            ("j".to_owned(), Value::Text("[1,2]".to_owned())),
+            ("s".to_owned(), Value::Text("[1,2]".to_owned())),
+        ]));
+        assert_eq!(json_text(&value, &DataType::Struct(fields), &settings()).unwrap(), r#"{"j":[1,2],"s":"[1,2]"}"#);
+    }
+
+    #[test]
+    fn test_extension_fields() {
+        let uuid: Vec<u8> = (0u8..16).collect();
+        let field = uuid_field("u");
+        assert_eq!(into_pg_field_type(&field).unwrap(), Type::UUID);
+        let value = PgValue::from_field_value(Value::Blob(uuid.clone()), &field, &settings()).unwrap();
+        assert_eq!(value.text().unwrap().unwrap(), "00010203-0405-0607-0809-0a0b0c0d0e0f");
+        let mut out = BytesMut::new();
+        value.to_sql(&Type::UUID, &mut out).unwrap();
+        assert_eq!(out.to_vec(), uuid);
+
+        let field = hugeint_field("h");
+        assert_eq!(into_pg_field_type(&field).unwrap(), Type::NUMERIC);
+        let raw = Value::Blob(i128::MIN.to_le_bytes().to_vec());
+        let value = PgValue::from_field_value(raw.clone(), &field, &settings()).unwrap();
+        assert_eq!(value, PgValue::NumericInt(NumericInt::from(i128::MIN)));
+        assert!(PgValue::from_field_value(Value::Blob(vec![0; 8]), &field, &settings()).is_err());
+        let max = Value::Blob(u128::MAX.to_le_bytes().to_vec());
+        let value = PgValue::from_field_value(max, &uhugeint_field("u"), &settings()).unwrap();
+        assert_eq!(value.text().unwrap().unwrap(), u128::MAX.to_string());
+
+        // 嵌套在 STRUCT 里的扩展类型同样按字段转换
+        let fields = Fields::from(vec![hugeint_field("h"), uuid_field("u")]);
+        let value = Value::Struct(OrderedMap::from(vec![("h".to_owned(), raw), ("u".to_owned(), Value::Blob(uuid))]));
+        assert_eq!(
+            json_text(&value, &DataType::Struct(fields), &settings()).unwrap(),
+            format!(r#"{{"h":{},"u":"00010203-0405-0607-0809-0a0b0c0d0e0f"}}"#, i128::MIN)
+        );
+    }
+
+    #[test]
+    fn test_json_param() {
+        assert_eq!(json_param(br#"{"a":1}"#, FieldFormat::Text, &Type::JSONB).unwrap(), r#"{"a":1}"#);
+        assert_eq!(json_param(b"\x01[1]", FieldFormat::Binary, &Type::JSONB).unwrap(), "[1]");
+        assert_eq!(json_param(b"[1]", FieldFormat::Binary, &Type::JSON).unwrap(), "[1]");
+        assert!(json_param(b"\x02[1]", FieldFormat::Binary, &Type::JSONB).is_err());
+    }
+
+    #[test]
+    fn test_bit_string() {
+        // DuckDB 中 '10101' 是 3 位填充加上 11110101
+        let bits = BitString::from_duckdb(&[3, 0b1111_0101]).unwrap();
+        assert_eq!(bits.text(), "10101");
+        assert_eq!(BitString::parse("10101"), Some(bits.clone()));
+        assert_eq!(BitString::from_duckdb(&[0]).unwrap().text(), "");
+        assert!(BitString::from_duckdb(&[9, 0]).is_none());
+
+        let mut out = BytesMut::new();
+        bits.to_sql(&Type::VARBIT, &mut out).unwrap();
+        assert_eq!(out.as_ref(), &[0, 0, 0, 5, 0b1010_1000]);
+        assert_eq!(BitString::from_sql(&Type::VARBIT, &out).unwrap(), bits);
+        assert_eq!(bit_param(&out, FieldFormat::Binary, &Type::VARBIT).unwrap(), "10101");
+        assert!(bit_param(b"102", FieldFormat::Text, &Type::VARBIT).is_err());
+    }
+
+    #[test]
+    fn test_scalar_params() {
+        let uuid: Vec<u8> = (0u8..16).collect();
+        assert_eq!(
+            text_param(&uuid, FieldFormat::Binary, &Type::UUID).unwrap(),
+            "00010203-0405-0607-0809-0a0b0c0d0e0f"
+        );
+        let mut numeric = BytesMut::new();
+        Decimal::new(-12345, 2).to_sql(&Type::NUMERIC, &mut numeric).unwrap();
+        assert_eq!(text_param(&numeric, FieldFormat::Binary, &Type::NUMERIC).unwrap(), "-123.45");
+        let mut date = BytesMut::new();
+        NaiveDate::from_ymd_opt(2024, 2, 29).unwrap().to_sql(&Type::DATE, &mut date).unwrap();
+        assert_eq!(text_param(&date, FieldFormat::Binary, &Type::DATE).unwrap(), "2024-02-29");
+        assert_eq!(text_param(b"2024-02-29", FieldFormat::Text, &Type::DATE).unwrap(), "2024-02-29");
+        assert!(text_param(&[1, 2], FieldFormat::Binary, &Type::UUID).is_err());
+    }
+
+    #[test]
+    fn test_bytea_param() {
+        assert_eq!(bytea_param(b"\\x01ff", FieldFormat::Text).unwrap(), vec![0x01, 0xff]);
+        assert_eq!(bytea_param(b"a\\\\b\\001", FieldFormat::Text).unwrap(), b"a\\b\x01".to_vec());
+        assert_eq!(bytea_param(&[0, 1], FieldFormat::Binary).unwrap(), vec![0, 1]);
+        assert!(bytea_param(b"\\x0", FieldFormat::Text).is_err());
+    }
+
+    #[test]
+    fn test_array_param_text() {
+        let literal = |text: &str| array_param_literal(text.as_bytes(), FieldFormat::Text, &Type::TEXT_ARRAY);
+        assert_eq!(literal("{1,2,NULL}").unwrap(), "[1, 2, NULL]");
+        assert_eq!(literal(r#"{{"a,b", c }, {"NULL",d}}"#).unwrap(), r#"[["a,b", c], ["NULL", d]]"#);
+        assert_eq!(literal("[1:2]={x,y}").unwrap(), "[x, y]");
+        assert_eq!(literal("{}").unwrap(), "[]");
+        assert!(literal("{1,2").is_err());
+    }
+
+    #[test]
+    fn test_array_value() {
+        let ints = Int32Array::from(vec![Some(1), None]);
+        assert_eq!(array_value(&ints, 0).unwrap(), Value::Int(1));
+        assert_eq!(array_value(&ints, 1).unwrap(), Value::Null);
+
+        let moods: DictionaryArray<UInt8Type> = vec!["ok", "sad", "ok"].into_iter().collect();
+        assert_eq!(array_value(&moods, 2).unwrap(), Value::Enum("ok".to_owned()));
+
+        let lists = ListArray::from_iter_primitive::<Int32Type, _, _>(vec![Some(vec![Some(1), None]), None]);
+        assert_eq!(array_value(&lists, 0).unwrap(), Value::List(vec![Value::Int(1), Value::Null]));
+        assert_eq!(array_value(&lists, 1).unwrap(), Value::Null);
+
+        let structs = StructArray::from(vec![
+            (Arc::new(Field::new("id", DataType::Int32, true)), Arc::new(Int32Array::from(vec![7])) as Arc<dyn Array>),
+            (Arc::new(Field::new("name", DataType::Utf8, true)), Arc::new(StringArray::from(vec!["a"])) as Arc<dyn Array>),
+        ]);
+        assert_eq!(
+            array_value(&structs, 0).unwrap(),
+            Value::Struct(OrderedMap::from(vec![
+                ("id".to_owned(), Value::Int(7)),
+                ("name".to_owned(), Value::Text("a".to_owned())),
+            ]))
+        );
+    }
+}
@@ -8,6 +8,12 @@ CREATE TABLE person (
 INSERT INTO person
 VALUES ('Pedro', 'happy'), ('Mark', NULL), ('Pagliacci', 'sad'), ('Mr. Mackey', 'ok');
 
--- SELECT *
--- FROM person
--- WHERE current_mood = 'sad';
+SELECT *
+FROM person
+WHERE current_mood = 'sad';
+
+SELECT t.typname, e.enumlabel, e.enumsortorder
+FROM pg_catalog.pg_type t
+JOIN pg_catalog.pg_enum e ON e.enumtypid = t.oid
+WHERE t.typname = 'mood'
+ORDER BY e.enumsortorder;
@@ -18,6 +18,22 @@ select
 from t_BIGINT;
 
 
+CREATE TABLE t_BIT (
+    a BIT,
+    b BITSTRING,
+);
+
+INSERT INTO t_BIT VALUES (
+    '10101'::BIT,
+    '0'::BITSTRING,
+);
+
+select 
+    a,
+    b,
+from t_BIT;
+
+
 CREATE TABLE t_BLOB (
     a BLOB,
     b BYTEA,
@@ -150,21 +166,66 @@ select
     c,
 from t_FLOAT;
 
--- CREATE TABLE t_HUGEINT (
---     a HUGEINT,
--- );
+CREATE TABLE t_HUGEINT (
+    a HUGEINT,
+    b INT128,
+);
 
--- INSERT INTO t_HUGEINT VALUES (
---     123::HUGEINT,
--- );
+INSERT INTO t_HUGEINT VALUES (
+    170141183460469231731687303715884105727::HUGEINT,
+    -170141183460469231731687303715884105727::INT128,
+);
 
--- select 
---     a,
--- from t_HUGEINT;
+select 
+    a,
+    b,
+from t_HUGEINT;
 
 
 SELECT cast(epoch(INTERVAL 1 YEAR) as integer);
 
+CREATE TABLE t_INTERVAL (
+    a INTERVAL,
+);
+
+INSERT INTO t_INTERVAL VALUES
+    (INTERVAL '1 year 2 months 3 days 04:05:06.789'),
+    (INTERVAL '-1 year -2 months 3 days -04:05:06'),
+    (INTERVAL '0 seconds');
+
+select 
+    a,
+from t_INTERVAL;
+
+SET IntervalStyle = 'iso_8601';
+
+select 
+    a,
+from t_INTERVAL;
+
+RESET IntervalStyle;
+
+
+CREATE TABLE t_JSON (
+    a JSON,
+    b JSONB,
+);
+
+INSERT INTO t_JSON VALUES (
+    '{"name": "duck", "tags": ["a", "b"], "info": {"age": 3}}',
+    '[1, 2, 3]'::jsonb,
+);
+
+select 
+    a,
+    b,
+    a ->> 'name',
+    a -> 'tags',
+    a #>> '{info,age}',
+    jsonb_array_length(b),
+    jsonb_build_object('k', b),
+from t_JSON;
+
 
 CREATE TABLE t_SMALLINT (
     a SMALLINT,
@@ -200,17 +261,17 @@ from t_TIME;
 
 
 
--- CREATE TABLE t_TIMETZ (
---     a TIMETZ,
--- );
+CREATE TABLE t_TIMETZ (
+    a TIMETZ,
+);
 
--- INSERT INTO t_TIMETZ VALUES (
---     TIMETZ '1992-09-20 11:30:00.123456-02:00';
--- );
+INSERT INTO t_TIMETZ VALUES (
+    TIMETZ '1992-09-20 11:30:00.123456-02:00',
+);
 
--- select 
---     a,
--- from t_TIMETZ;
+select 
+    a,
+from t_TIMETZ;
 
 CREATE TABLE t_TIMESTAMP (
     a TIMESTAMP,
@@ -228,6 +289,45 @@ select
 from t_TIMESTAMP;
 
 
+CREATE TABLE t_TIMESTAMP_UNIT (
+    a TIMESTAMP_S,
+    b TIMESTAMP_MS,
+    c TIMESTAMP_NS,
+);
+
+INSERT INTO t_TIMESTAMP_UNIT VALUES (
+    TIMESTAMP_S '1992-09-20 11:30:00.123456789',
+    TIMESTAMP_MS '1992-09-20 11:30:00.123456789',
+    TIMESTAMP_NS '1992-09-20 11:30:00.123456789',
+);
+
+select 
+    a,
+    b,
+    c,
+from t_TIMESTAMP_UNIT;
+
+
+SET TimeZone = 'Asia/Shanghai';
+
+CREATE TABLE t_TIMESTAMPTZ (
+    a TIMESTAMPTZ,
+    b TIMESTAMP WITH TIME ZONE,
+);
+
+INSERT INTO t_TIMESTAMPTZ VALUES (
+    TIMESTAMPTZ '1992-09-20 11:30:00.123456+00',
+    TIMESTAMP WITH TIME ZONE '1992-09-20 11:30:00.123456-02:00',
+);
+
+select 
+    a,
+    b,
+from t_TIMESTAMPTZ;
+
+RESET TimeZone;
+
+
 
 CREATE TABLE t_TINYINT (
     a TINYINT,
@@ -254,11 +354,79 @@ INSERT INTO t_UINTEGER VALUES (
     1::UINTEGER,
 );
 
+INSERT INTO t_UINTEGER VALUES (
+    4294967295::UINTEGER,
+);
+
 select 
     a,
 from t_UINTEGER;
 
 
+CREATE TABLE t_UBIGINT (
+    a UBIGINT,
+    b UINT64,
+);
+
+INSERT INTO t_UBIGINT VALUES (
+    18446744073709551615::UBIGINT,
+    1::UINT64,
+);
+
+select 
+    a,
+    b,
+from t_UBIGINT;
+
+
+CREATE TABLE t_UHUGEINT (
+    a UHUGEINT,
+    b UINT128,
+);
+
+INSERT INTO t_UHUGEINT VALUES (
+    99999999999999999999999999999999999999::UHUGEINT,
+    340282366920938463463374607431768211455::UINT128,
+);
+
+select 
+    a,
+    b,
+from t_UHUGEINT;
+
+
+CREATE TABLE t_USMALLINT (
+    a USMALLINT,
+    b UINT16,
+);
+
+INSERT INTO t_USMALLINT VALUES (
+    65535::USMALLINT,
+    1::UINT16,
+);
+
+select 
+    a,
+    b,
+from t_USMALLINT;
+
+
+CREATE TABLE t_UTINYINT (
+    a UTINYINT,
+    b UINT8,
+);
+
+INSERT INTO t_UTINYINT VALUES (
+    255::UTINYINT,
+    1::UINT8,
+);
+
+select 
+    a,
+    b,
+from t_UTINYINT;
+
+
 -- VARCHAR, CHAR, BPCHAR, TEXT, STRING
 CREATE TABLE t_VARCHAR (
     a VARCHAR,
@@ -1,11 +1,80 @@
--- CREATE TABLE t_ARRAY (
---     a INTEGER[],
--- );
+-- https://duckdb.org/docs/sql/data_types/overview
+CREATE TABLE t_ARRAY (
+    a INTEGER[3],
+    b VARCHAR[2],
+);
 
--- INSERT INTO t_ARRAY VALUES (
---     array_value(1, 2, 3),
--- );
+INSERT INTO t_ARRAY VALUES (
+    array_value(1, 2, 3),
+    array_value('a b', NULL),
+);
 
--- select 
---     a,
--- from t_ARRAY;
+select 
+    a,
+    b,
+from t_ARRAY;
+
+
+CREATE TABLE t_LIST (
+    a INTEGER[],
+    b VARCHAR[],
+    c INTEGER[][],
+    d TIMESTAMP[],
+);
+
+INSERT INTO t_LIST VALUES (
+    [1, 2, NULL],
+    ['a', 'b,c', '"d"', 'NULL'],
+    [[1, 2], [3, 4]],
+    [TIMESTAMP '1992-09-20 11:30:00.123456'],
+);
+
+INSERT INTO t_LIST VALUES (
+    [],
+    NULL,
+    [],
+    [NULL],
+);
+
+select 
+    a,
+    b,
+    c,
+    d,
+from t_LIST;
+
+
+CREATE TABLE t_STRUCT (
+    a STRUCT(id INTEGER, name VARCHAR, tags VARCHAR[]),
+);
+
+INSERT INTO t_STRUCT VALUES ({'id': 1, 'name': 'a "b"', 'tags': ['x', NULL]});
+INSERT INTO t_STRUCT VALUES (NULL);
+
+select 
+    a,
+from t_STRUCT;
+
+
+CREATE TABLE t_MAP (
+    a MAP(INTEGER, VARCHAR),
+);
+
+INSERT INTO t_MAP VALUES (MAP {1: 'a', 2: NULL});
+INSERT INTO t_MAP VALUES (MAP {});
+
+select 
+    a,
+from t_MAP;
+
+
+CREATE TABLE t_UNION (
+    a UNION(num INTEGER, str VARCHAR),
+);
+
+INSERT INTO t_UNION VALUES (1);
+INSERT INTO t_UNION VALUES ('two');
+
+select 
+    a,
+from t_UNION;